@@ -0,0 +1,199 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::{self, Rng};
+
+// Crockford's base 32, which leaves out letters that look like digits
+static CODE_DIGITS: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Where a game's seed came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeedSource {
+    Random,
+    Chosen,
+    /// The day's challenge, which is the same for everyone playing it.
+    Daily(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub seed: u64,
+    pub source: SeedSource,
+}
+
+impl Challenge {
+    pub fn random() -> Challenge {
+        Challenge { seed: rand::thread_rng().gen(), source: SeedSource::Random }
+    }
+
+    pub fn chosen(seed: u64) -> Challenge {
+        Challenge { seed, source: SeedSource::Chosen }
+    }
+
+    /// The seed is made from `date`, although any text will do.
+    pub fn daily(date: &str) -> Challenge {
+        // FNV-1a, which unlike the standard library's hasher is guaranteed to
+        // give the same answer everywhere
+        let mut seed: u64 = 0xcbf29ce484222325;
+        for &byte in format!("daily {}", date).as_bytes() {
+            seed ^= byte as u64;
+            seed = seed.wrapping_mul(0x100000001b3);
+        }
+        Challenge { seed, source: SeedSource::Daily(String::from(date)) }
+    }
+
+    /// A number, or a seed code as shown in the game. Anything thirteen
+    /// digits long is a code, even if it's all numbers.
+    pub fn parse(arg: &str) -> Option<Challenge> {
+        let seed = if arg.chars().filter(|&ch| ch != '-').count() == 13 {
+            parse_seed_code(arg)
+        } else {
+            arg.parse().ok().or_else(|| parse_seed_code(arg))
+        };
+        seed.map(Challenge::chosen)
+    }
+
+    /// The game after this one. Chosen and daily seeds are played again, and
+    /// random ones aren't.
+    pub fn next(&self) -> Challenge {
+        match self.source {
+            SeedSource::Random => Challenge::random(),
+            _ => self.clone(),
+        }
+    }
+
+    pub fn code(&self) -> String {
+        seed_code(self.seed)
+    }
+
+    /// What's shown on screen and recorded with the score.
+    pub fn label(&self) -> String {
+        match self.source {
+            SeedSource::Daily(ref date) => format!("daily {} ({})", date, self.code()),
+            _ => format!("seed {}", self.code()),
+        }
+    }
+}
+
+/// Thirteen base 32 digits, in groups that are easier to read out.
+pub fn seed_code(seed: u64) -> String {
+    let mut code = String::new();
+    for i in (0..13).rev() {
+        code.push(CODE_DIGITS[((seed >> (i*5)) & 31) as usize] as char);
+        if i == 8 || i == 4 {
+            code.push('-');
+        }
+    }
+    code
+}
+
+/// Ignores case and dashes, and reads I and L as 1 and O as 0 like Crockford
+/// intended.
+pub fn parse_seed_code(code: &str) -> Option<u64> {
+    if code.is_empty() {
+        return None;
+    }
+    let mut seed: u64 = 0;
+    for ch in code.chars().filter(|&ch| ch != '-') {
+        let ch = match ch.to_ascii_uppercase() {
+            'I' | 'L' => '1',
+            'O' => '0',
+            ch => ch,
+        };
+        let digit = CODE_DIGITS.iter().position(|&d| d as char == ch)? as u64;
+        seed = seed.checked_mul(32)?.checked_add(digit)?;
+    }
+    Some(seed)
+}
+
+/// Today's date in UTC, as YYYY-MM-DD.
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() / 86400).unwrap_or(0);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Howard Hinnant's algorithm, for days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era*146097;
+    let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096) / 365;
+    let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+    let mp = (5*day_of_year + 2) / 153;
+    let day = day_of_year - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era*400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Adds a line to the scores file: the date, the seed code, where the seed
+/// came from, the map settings, how the game ended and the diamonds, separated
+/// by tabs.
+pub fn record_score(path: &str, challenge: &Challenge, map: &str, escaped: bool, diamonds: i32)
+    -> io::Result<()>
+{
+    let source = match challenge.source {
+        SeedSource::Random => String::from("random"),
+        SeedSource::Chosen => String::from("chosen"),
+        SeedSource::Daily(ref date) => format!("daily {}", date),
+    };
+    let outcome = if escaped { "escaped" } else { "died" };
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}\t{}\t{}\t{}\t{}\t{}", today(), challenge.code(), source, map, outcome, diamonds)
+}
+
+/// The most diamonds anyone has escaped with on the same seed and map.
+pub fn best_score(path: &str, challenge: &Challenge, map: &str) -> Option<i32> {
+    let text = fs::read_to_string(path).ok()?;
+    let code = challenge.code();
+    text.lines()
+        .map(|line| line.split('\t').collect::<Vec<_>>())
+        .filter(|fields| fields.len() == 6 && fields[1] == code && fields[3] == map && fields[4] == "escaped")
+        .filter_map(|fields| fields[5].parse().ok())
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_codes_round_trip() {
+        assert_eq!(seed_code(0), "00000-0000-0000");
+        assert_eq!(seed_code(u64::MAX), "FZZZZ-ZZZZ-ZZZZ");
+        for &seed in &[0, 1, 31, 32, 1234, 0x0123_4567_89ab_cdef, u64::MAX] {
+            assert_eq!(parse_seed_code(&seed_code(seed)), Some(seed));
+        }
+    }
+
+    #[test]
+    fn seed_codes_forgive_typos() {
+        let seed = parse_seed_code("10ABC-DEFG-HJKM");
+        assert_eq!(parse_seed_code("lOabc-defg-hjkm"), seed);
+        assert_eq!(parse_seed_code("IOABCDEFGHJKM"), seed);
+        assert_eq!(parse_seed_code(""), None);
+        assert_eq!(parse_seed_code("10ABC-DEFG-HJKU"), None);
+        // More than 64 bits
+        assert_eq!(parse_seed_code("ZZZZZ-ZZZZ-ZZZZ"), None);
+    }
+
+    #[test]
+    fn parses_numbers_and_codes() {
+        assert_eq!(Challenge::parse("1234"), Some(Challenge::chosen(1234)));
+        assert_eq!(Challenge::parse("00000-0000-0Z"), Some(Challenge::chosen(31)));
+        assert_eq!(Challenge::parse("0000000001234"), Some(Challenge::chosen(32*32*32 + 2*32*32 + 3*32 + 4)));
+        assert_eq!(Challenge::parse("00000-0000-1234"), Challenge::parse("0000000001234"));
+        assert_eq!(Challenge::parse("not a seed"), None);
+    }
+
+    #[test]
+    fn counts_days_since_1970() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+
+use super::Id;
+
+/// Keeps track of which ids of a given type are currently in use.
+pub trait IdAllocator<I>: Default + Clone {
+    fn is_live(&self, id: I) -> bool;
+
+    /// Called after an id has been despawned.
+    fn free(&mut self, _id: I) {}
+
+    /// Undoes `free` when a despawn gets rolled back.
+    fn revive(&mut self, _id: I) {}
+}
+
+/// Hands out fresh ids, so the `world!` storage can `spawn` them.
+pub trait Allocate<I>: IdAllocator<I> {
+    fn allocate(&mut self) -> I;
+}
+
+/// For ids like positions that are always valid.
+#[derive(Default, Clone)]
+pub struct Unallocated;
+
+impl<I> IdAllocator<I> for Unallocated {
+    fn is_live(&self, _id: I) -> bool { true }
+}
+
+/// An id made up of a slot index and the generation of that slot, so a
+/// handle to something that has since been despawned won't match whatever
+/// reuses the slot.
+pub trait GenerationalId: Id {
+    fn from_parts(index: u32, generation: u32) -> Self;
+    fn index(self) -> u32;
+    fn generation(self) -> u32;
+}
+
+#[derive(Clone)]
+struct Slot {
+    generation: u32,
+    live: bool,
+}
+
+// How many slots must be free before any of them get reused
+const MIN_FREE_SLOTS: usize = 32;
+
+#[derive(Default, Clone)]
+pub struct GenerationalAllocator {
+    slots: Vec<Slot>,
+    free: VecDeque<u32>,
+}
+
+impl<I: GenerationalId> IdAllocator<I> for GenerationalAllocator {
+    fn is_live(&self, id: I) -> bool {
+        self.slots.get(id.index() as usize)
+            .map(|slot| slot.live && slot.generation == id.generation())
+            .unwrap_or(false)
+    }
+
+    fn free(&mut self, id: I) {
+        if self.is_live(id) {
+            let slot = &mut self.slots[id.index() as usize];
+            slot.live = false;
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push_back(id.index());
+        }
+    }
+
+    fn revive(&mut self, id: I) {
+        if let Some(slot) = self.slots.get_mut(id.index() as usize) {
+            if !slot.live {
+                slot.live = true;
+                slot.generation = id.generation();
+                self.free.retain(|&i| i != id.index());
+            }
+        }
+    }
+}
+
+impl<I: GenerationalId> Allocate<I> for GenerationalAllocator {
+    fn allocate(&mut self) -> I {
+        // Reuse the oldest free slot first, so a stale id lingers for as
+        // long as possible before its slot comes back around.
+        let index = match self.free.len() {
+            n if n < MIN_FREE_SLOTS => {
+                self.slots.push(Slot { generation: 0, live: false });
+                self.slots.len() as u32 - 1
+            }
+            _ => self.free.pop_front().unwrap(),
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.live = true;
+        I::from_parts(index, slot.generation)
+    }
+}
+
+/// The allocator for one id type, as stored in the `world!` struct.
+pub struct IdAllocation<I: Id>(pub I::Allocator);
+
+impl<I: Id> Clone for IdAllocation<I> {
+    fn clone(&self) -> Self {
+        IdAllocation(self.0.clone())
+    }
+}
+
+impl<I: Id> Default for IdAllocation<I> {
+    fn default() -> Self {
+        IdAllocation(Default::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    struct TestId(u32, u32);
+
+    impl Id for TestId {
+        type Allocator = GenerationalAllocator;
+    }
+
+    impl GenerationalId for TestId {
+        fn from_parts(index: u32, generation: u32) -> TestId { TestId(index, generation) }
+        fn index(self) -> u32 { self.0 }
+        fn generation(self) -> u32 { self.1 }
+    }
+
+    fn allocate(a: &mut GenerationalAllocator, n: usize) -> Vec<TestId> {
+        (0..n).map(|_| a.allocate()).collect()
+    }
+
+    #[test]
+    fn slots_wait_until_enough_are_free() {
+        let mut a = GenerationalAllocator::default();
+        let ids = allocate(&mut a, MIN_FREE_SLOTS);
+        for &id in &ids[..MIN_FREE_SLOTS - 1] {
+            a.free(id);
+        }
+        let id: TestId = a.allocate();
+        assert_eq!(id, TestId(MIN_FREE_SLOTS as u32, 0));
+
+        a.free(ids[MIN_FREE_SLOTS - 1]);
+        a.free(id);
+        let id: TestId = a.allocate();
+        assert_eq!(id, TestId(0, 1));
+    }
+
+    #[test]
+    fn reused_slots_dont_revive_old_ids() {
+        let mut a = GenerationalAllocator::default();
+        let ids = allocate(&mut a, MIN_FREE_SLOTS);
+        for &id in &ids {
+            a.free(id);
+        }
+        let reused: TestId = a.allocate();
+        assert_eq!(reused.index(), ids[0].index());
+        assert!(a.is_live(reused));
+        assert!(!a.is_live(ids[0]));
+
+        // Freeing a stale id mustn't free the slot out from under its new owner
+        a.free(ids[0]);
+        assert!(a.is_live(reused));
+    }
+
+    #[test]
+    fn revive_undoes_free() {
+        let mut a = GenerationalAllocator::default();
+        let ids = allocate(&mut a, MIN_FREE_SLOTS + 1);
+        for &id in &ids[..MIN_FREE_SLOTS] {
+            a.free(id);
+        }
+        a.revive(ids[0]);
+        assert!(a.is_live(ids[0]));
+        // Only MIN_FREE_SLOTS - 1 are free now, so nothing is reused
+        let id: TestId = a.allocate();
+        assert_eq!(id.index(), MIN_FREE_SLOTS as u32 + 1);
+    }
+}
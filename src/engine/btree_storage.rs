@@ -3,6 +3,7 @@ use std::collections::btree_map::{Keys, Iter, IterMut};
 
 use super::*;
 
+#[derive(Clone)]
 pub struct BTreeStorage<I: Id + Ord, C: Component>(BTreeMap<I, C>);
 
 impl<I: Id + Ord, C: Component> Default for BTreeStorage<I, C> {
@@ -47,18 +48,6 @@ impl<I: Id + Ord, C: Component> ComponentStorage<I, C> for BTreeStorage<I, C> {
     fn clear(&mut self) {
         self.0.clear()
     }
-
-    // fn ids<'a>(&'a self) -> Box<Iterator<Item=I> + 'a> {
-    //     Box::new(self.0.keys().map(|&id| id))
-    // }
-
-    // fn iter<'a>(&'a self) -> Box<Iterator<Item=(I, &C)> + 'a> {
-    //     Box::new(self.0.iter().map(|(&id, c)| (id, c)))
-    // }
-
-    // fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(I, &mut C)> + 'a> {
-    //     Box::new(self.0.iter_mut().map(|(&id, c)| (id, c)))
-    // }
 }
 
 pub struct IdsMap<'a, I: Id + Ord + 'a, C: Component + 'a>(Keys<'a, I, C>);
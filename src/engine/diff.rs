@@ -0,0 +1,89 @@
+use std::fmt::Debug;
+
+use super::*;
+
+/// How one component or resource differs between two snapshots. Values are
+/// formatted with `Debug`, since their types vary.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Changed { old: String, new: String },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ComponentChange<I> {
+    pub id: I,
+    pub component: &'static str,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ResourceChange {
+    pub resource: &'static str,
+    pub change: Change,
+}
+
+fn changed<T: Debug>(old: &T, new: &T) -> Change {
+    Change::Changed {
+        old: format!("{:?}", old),
+        new: format!("{:?}", new),
+    }
+}
+
+struct DiffComponents<'a, S: 'a, I> {
+    new: &'a S,
+    changes: Vec<ComponentChange<I>>,
+}
+
+impl<'a, S: EntityStorage<I>, I: Id> VisitComponentTypes<S, I> for DiffComponents<'a, S, I> {
+    fn visit<C: Component>(&mut self, old: &S) where S: EntityComponent<I, C> {
+        let component = short_type_name::<C>();
+        let (old, new) = (old.component::<C>(), self.new.component::<C>());
+        for (id, c) in old.iter() {
+            let change = match new.get(id) {
+                None => Change::Removed(format!("{:?}", c)),
+                Some(n) if n != c => changed(c, n),
+                Some(_) => continue,
+            };
+            self.changes.push(ComponentChange { id, component, change });
+        }
+        for (id, n) in new.iter() {
+            if !old.has(id) {
+                let change = Change::Added(format!("{:?}", n));
+                self.changes.push(ComponentChange { id, component, change });
+            }
+        }
+    }
+}
+
+struct DiffResources<'a, S: 'a> {
+    new: &'a S,
+    changes: Vec<ResourceChange>,
+}
+
+impl<'a, S: World> VisitResources<S> for DiffResources<'a, S> {
+    fn visit<R: Resource>(&mut self, old: &S) where S: HasResource<R> {
+        let (old, new) = (old.resource::<R>(), self.new.resource::<R>());
+        if old != new {
+            self.changes.push(ResourceChange {
+                resource: short_type_name::<R>(),
+                change: changed(old, new),
+            });
+        }
+    }
+}
+
+/// Every component of an `I` that differs between the two worlds, grouped
+/// by component type in the order the world declares them.
+pub fn diff<S: EntityStorage<I>, I: Id>(old: &S, new: &S) -> Vec<ComponentChange<I>> {
+    let mut diff = DiffComponents { new, changes: vec![] };
+    old.visit_component_types(&mut diff);
+    diff.changes
+}
+
+pub fn diff_resources<S: World>(old: &S, new: &S) -> Vec<ResourceChange> {
+    let mut diff = DiffResources { new, changes: vec![] };
+    old.visit_resources(&mut diff);
+    diff.changes
+}
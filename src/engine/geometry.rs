@@ -1,4 +1,6 @@
-use super::Id;
+use std::ops::{Add, AddAssign, Neg, Sub};
+
+use super::{Id, Unallocated};
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
 pub enum Direction {
@@ -39,16 +41,7 @@ pub static DIAGONAL_DIRECTIONS: [Direction; 4] = [
 
 impl Direction {
     pub fn reverse(self) -> Direction {
-        match self {
-            Direction::North => Direction::South,
-            Direction::NorthEast => Direction::SouthWest,
-            Direction::East => Direction::West,
-            Direction::SouthEast => Direction::NorthWest,
-            Direction::South => Direction::North,
-            Direction::SouthWest => Direction::NorthEast,
-            Direction::West => Direction::East,
-            Direction::NorthWest => Direction::SouthEast,
-        }
+        self.rotate(4)
     }
 
     pub fn is_orthogonal(&self) -> bool {
@@ -57,6 +50,79 @@ impl Direction {
         || self == &Direction::South
         || self == &Direction::West
     }
+
+    /// Turns clockwise by `eighths` eighths of a full turn, or counterclockwise
+    /// if it's negative.
+    pub fn rotate(self, eighths: i32) -> Direction {
+        let index = ALL_DIRECTIONS.iter().position(|&d| d == self).unwrap() as i32;
+        ALL_DIRECTIONS[(index + eighths).rem_euclid(8) as usize]
+    }
+
+    pub fn offset(self) -> Offset {
+        let (x, y) = match self {
+            Direction::North => (0, -1),
+            Direction::NorthEast => (1, -1),
+            Direction::East => (1, 0),
+            Direction::SouthEast => (1, 1),
+            Direction::South => (0, 1),
+            Direction::SouthWest => (-1, 1),
+            Direction::West => (-1, 0),
+            Direction::NorthWest => (-1, -1),
+        };
+        Offset { x, y }
+    }
+}
+
+/// The difference between two positions.
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Default)]
+pub struct Offset {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Offset {
+    /// -1, 0 or 1 along each axis.
+    pub fn signum(self) -> Offset {
+        Offset { x: self.x.signum(), y: self.y.signum() }
+    }
+
+    pub fn length_sq(self) -> i32 {
+        self.x*self.x + self.y*self.y
+    }
+
+    /// How many steps it takes to go this far, counting diagonal steps.
+    pub fn chebyshev(self) -> i32 {
+        ::std::cmp::max(self.x.abs(), self.y.abs())
+    }
+
+    /// How many steps it takes to go this far without diagonal steps.
+    pub fn manhattan(self) -> i32 {
+        self.x.abs() + self.y.abs()
+    }
+}
+
+impl Add for Offset {
+    type Output = Offset;
+
+    fn add(self, o: Offset) -> Offset {
+        Offset { x: self.x + o.x, y: self.y + o.y }
+    }
+}
+
+impl Sub for Offset {
+    type Output = Offset;
+
+    fn sub(self, o: Offset) -> Offset {
+        Offset { x: self.x - o.x, y: self.y - o.y }
+    }
+}
+
+impl Neg for Offset {
+    type Output = Offset;
+
+    fn neg(self) -> Offset {
+        Offset { x: -self.x, y: -self.y }
+    }
 }
 
 // deriving Ord here is probably the wrong thing to do, but whatever,
@@ -67,25 +133,179 @@ pub struct Position {
     pub y: i32,
 }
 
-impl Id for Position {}
+impl Id for Position {
+    type Allocator = Unallocated;
+}
 
 impl Position {
     pub fn step(self, d: Direction) -> Position {
-        match d {
-            Direction::North => Position { x: self.x, y: self.y - 1 },
-            Direction::NorthEast => Position { x: self.x + 1, y: self.y - 1 },
-            Direction::East => Position { x: self.x + 1, y: self.y },
-            Direction::SouthEast => Position { x: self.x + 1, y: self.y + 1 },
-            Direction::South => Position { x: self.x, y: self.y + 1 },
-            Direction::SouthWest => Position { x: self.x - 1, y: self.y + 1 },
-            Direction::West => Position { x: self.x - 1, y: self.y },
-            Direction::NorthWest => Position { x: self.x - 1, y: self.y - 1 },
-        }
+        self + d.offset()
     }
 
     pub fn distance_sq(self, p: Position) -> i32 {
-        let x_offset = self.x - p.x;
-        let y_offset = self.y - p.y;
-        x_offset*x_offset + y_offset*y_offset
+        (self - p).length_sq()
+    }
+
+    pub fn chebyshev_distance(self, p: Position) -> i32 {
+        (self - p).chebyshev()
+    }
+
+    pub fn manhattan_distance(self, p: Position) -> i32 {
+        (self - p).manhattan()
+    }
+
+    /// Positions no further than `radius` from `self` as the crow flies, row
+    /// by row from the top left.
+    pub fn circle(self, radius: i32) -> impl Iterator<Item=Position> {
+        self.square(radius).filter(move |&p| self.distance_sq(p) <= radius*radius)
+    }
+
+    /// Positions exactly `radius` steps from `self`, counting diagonal steps,
+    /// row by row from the top left.
+    pub fn ring(self, radius: i32) -> impl Iterator<Item=Position> {
+        self.square(radius).filter(move |&p| self.chebyshev_distance(p) == radius)
+    }
+
+    fn square(self, radius: i32) -> impl Iterator<Item=Position> {
+        (-radius..=radius).flat_map(move |y| {
+            (-radius..=radius).map(move |x| self + Offset { x, y })
+        })
+    }
+}
+
+impl Add<Offset> for Position {
+    type Output = Position;
+
+    fn add(self, o: Offset) -> Position {
+        Position { x: self.x + o.x, y: self.y + o.y }
+    }
+}
+
+impl AddAssign<Offset> for Position {
+    fn add_assign(&mut self, o: Offset) {
+        *self = *self + o;
+    }
+}
+
+impl Sub<Offset> for Position {
+    type Output = Position;
+
+    fn sub(self, o: Offset) -> Position {
+        self + -o
+    }
+}
+
+impl Sub for Position {
+    type Output = Offset;
+
+    fn sub(self, p: Position) -> Offset {
+        Offset { x: self.x - p.x, y: self.y - p.y }
+    }
+}
+
+impl Position {
+    /// Positions along a Bresenham line from `self` to `end`, excluding `self`
+    /// and including `end`.
+    pub fn line_to(self, end: Position) -> Line {
+        let mut line = self.ray_towards(end);
+        line.remaining = Some(self.chebyshev_distance(end));
+        line
+    }
+
+    /// Like `line_to`, but keeps going past `target` indefinitely.
+    pub fn ray_towards(self, target: Position) -> Line {
+        let offset = target - self;
+        let (dx, dy) = (offset.x.abs(), -offset.y.abs());
+        let step = offset.signum();
+        Line {
+            pos: self,
+            dx,
+            dy,
+            sx: step.x,
+            sy: step.y,
+            err: dx + dy,
+            remaining: None,
+        }
+    }
+}
+
+pub struct Line {
+    pos: Position,
+    dx: i32,
+    dy: i32,
+    sx: i32,
+    sy: i32,
+    err: i32,
+    remaining: Option<i32>,
+}
+
+impl Iterator for Line {
+    type Item = Position;
+
+    fn next(&mut self) -> Option<Position> {
+        match self.remaining {
+            Some(0) => return None,
+            Some(ref mut n) => { *n -= 1; }
+            None => {}
+        }
+        if self.dx == 0 && self.dy == 0 {
+            self.remaining = Some(0);
+            return None;
+        }
+        let e2 = 2*self.err;
+        if e2 >= self.dy {
+            self.err += self.dy;
+            self.pos.x += self.sx;
+        }
+        if e2 <= self.dx {
+            self.err += self.dx;
+            self.pos.y += self.sy;
+        }
+        Some(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotating_wraps_around() {
+        assert_eq!(Direction::North.rotate(2), Direction::East);
+        assert_eq!(Direction::North.rotate(-1), Direction::NorthWest);
+        assert_eq!(Direction::West.rotate(10), Direction::North);
+        for &dir in &ALL_DIRECTIONS {
+            assert_eq!(dir.reverse().offset(), -dir.offset());
+        }
+    }
+
+    fn pos(x: i32, y: i32) -> Position {
+        Position { x, y }
+    }
+
+    #[test]
+    fn lines_run_from_start_to_end() {
+        let origin = pos(0, 0);
+        assert_eq!(origin.line_to(pos(3, 1)).collect::<Vec<_>>(), vec![pos(1, 0), pos(2, 1), pos(3, 1)]);
+        assert_eq!(origin.line_to(pos(-2, 2)).collect::<Vec<_>>(), vec![pos(-1, 1), pos(-2, 2)]);
+        assert_eq!(origin.line_to(pos(0, -3)).collect::<Vec<_>>(), vec![pos(0, -1), pos(0, -2), pos(0, -3)]);
+        assert_eq!(origin.line_to(origin).count(), 0);
+        for end in origin.ring(5) {
+            let line: Vec<_> = origin.line_to(end).collect();
+            assert_eq!(line.len(), 5);
+            assert_eq!(line.last(), Some(&end));
+            // Every step is to a neighbour
+            assert!(line.windows(2).all(|w| w[0].chebyshev_distance(w[1]) == 1));
+        }
+    }
+
+    #[test]
+    fn rays_keep_going() {
+        let origin = pos(0, 0);
+        let ray: Vec<_> = origin.ray_towards(pos(2, 1)).take(6).collect();
+        assert_eq!(&ray[..2], &origin.line_to(pos(2, 1)).collect::<Vec<_>>()[..]);
+        assert_eq!(ray[3], pos(4, 2));
+        assert_eq!(ray[5], pos(6, 3));
+        assert_eq!(origin.ray_towards(origin).next(), None);
     }
 }
@@ -0,0 +1,348 @@
+use std::marker::PhantomData;
+
+use super::*;
+
+// Chunks are CHUNK_SIZE tiles on a side
+const CHUNK_BITS: i32 = 4;
+const CHUNK_SIZE: i32 = 1 << CHUNK_BITS;
+
+#[derive(Clone)]
+struct Chunk<C> {
+    cells: Vec<Option<C>>,
+    count: usize,
+}
+
+impl<C> Chunk<C> {
+    fn new() -> Self {
+        Chunk {
+            cells: (0..CHUNK_SIZE*CHUNK_SIZE).map(|_| None).collect(),
+            count: 0,
+        }
+    }
+}
+
+/// Dense storage for components keyed by `Position`, as a growable grid of
+/// fixed size chunks. Lookups are a couple of array indexes instead of a
+/// tree search, and clearing keeps the chunks allocated.
+///
+/// Iterates over chunks in column order, and over each chunk's tiles in
+/// column order, so the order is predictable but isn't `Position` order.
+pub struct GridStorage<I, C> {
+    // Chunk coordinates of chunks[0]
+    min_cx: i32,
+    min_cy: i32,
+    // Size of the grid in chunks
+    width: i32,
+    height: i32,
+    chunks: Vec<Option<Box<Chunk<C>>>>,
+    count: usize,
+    _id: PhantomData<I>,
+}
+
+impl<I, C: Clone> Clone for GridStorage<I, C> {
+    fn clone(&self) -> Self {
+        GridStorage {
+            min_cx: self.min_cx,
+            min_cy: self.min_cy,
+            width: self.width,
+            height: self.height,
+            chunks: self.chunks.clone(),
+            count: self.count,
+            _id: PhantomData,
+        }
+    }
+}
+
+impl<I, C> Default for GridStorage<I, C> {
+    fn default() -> Self {
+        GridStorage {
+            min_cx: 0,
+            min_cy: 0,
+            width: 0,
+            height: 0,
+            chunks: Vec::new(),
+            count: 0,
+            _id: PhantomData,
+        }
+    }
+}
+
+fn split(pos: Position) -> (i32, i32, usize) {
+    let (cx, cy) = (pos.x >> CHUNK_BITS, pos.y >> CHUNK_BITS);
+    let (lx, ly) = (pos.x & (CHUNK_SIZE - 1), pos.y & (CHUNK_SIZE - 1));
+    (cx, cy, (lx*CHUNK_SIZE + ly) as usize)
+}
+
+impl<I, C> GridStorage<I, C> {
+    fn chunk_index(&self, cx: i32, cy: i32) -> Option<usize> {
+        let (dx, dy) = (cx - self.min_cx, cy - self.min_cy);
+        if dx < 0 || dy < 0 || dx >= self.width || dy >= self.height {
+            None
+        } else {
+            Some((dx*self.height + dy) as usize)
+        }
+    }
+
+    fn chunk_position(&self, index: usize) -> (i32, i32) {
+        let index = index as i32;
+        (self.min_cx + index / self.height, self.min_cy + index % self.height)
+    }
+
+    fn cell(&self, pos: Position) -> Option<&Option<C>> {
+        let (cx, cy, cell) = split(pos);
+        let chunk = self.chunks[self.chunk_index(cx, cy)?].as_ref()?;
+        Some(&chunk.cells[cell])
+    }
+
+    // Grows the grid to cover (cx, cy) if needed.
+    fn chunk_mut(&mut self, cx: i32, cy: i32) -> &mut Chunk<C> {
+        if self.chunk_index(cx, cy).is_none() {
+            let (min_cx, min_cy, max_cx, max_cy) = if self.chunks.is_empty() {
+                (cx, cy, cx, cy)
+            } else {
+                (
+                    ::std::cmp::min(self.min_cx, cx),
+                    ::std::cmp::min(self.min_cy, cy),
+                    ::std::cmp::max(self.min_cx + self.width - 1, cx),
+                    ::std::cmp::max(self.min_cy + self.height - 1, cy),
+                )
+            };
+            let mut grown = GridStorage {
+                min_cx,
+                min_cy,
+                width: max_cx - min_cx + 1,
+                height: max_cy - min_cy + 1,
+                chunks: vec![],
+                count: self.count,
+                _id: PhantomData,
+            };
+            grown.chunks = (0..grown.width*grown.height).map(|_| None).collect();
+            let old_chunks = ::std::mem::take(&mut self.chunks);
+            for (index, chunk) in old_chunks.into_iter().enumerate() {
+                if chunk.is_some() {
+                    let (old_cx, old_cy) = self.chunk_position(index);
+                    let new_index = grown.chunk_index(old_cx, old_cy).unwrap();
+                    grown.chunks[new_index] = chunk;
+                }
+            }
+            *self = grown;
+        }
+        let index = self.chunk_index(cx, cy).unwrap();
+        self.chunks[index].get_or_insert_with(|| Box::new(Chunk::new()))
+    }
+}
+
+impl<C: Component> ComponentStorage<Position, C> for GridStorage<Position, C> {
+    fn new() -> Self {
+        Default::default()
+    }
+
+    fn has(&self, id: Position) -> bool {
+        self.get(id).is_some()
+    }
+
+    fn get(&self, id: Position) -> Option<&C> {
+        self.cell(id).and_then(Option::as_ref)
+    }
+
+    fn get_mut(&mut self, id: Position) -> Option<&mut C> {
+        let (cx, cy, cell) = split(id);
+        let index = self.chunk_index(cx, cy)?;
+        self.chunks[index].as_mut()?.cells[cell].as_mut()
+    }
+
+    fn insert(&mut self, id: Position, c: C) -> Option<C> {
+        let (cx, cy, cell) = split(id);
+        let old = {
+            let chunk = self.chunk_mut(cx, cy);
+            let old = chunk.cells[cell].replace(c);
+            if old.is_none() {
+                chunk.count += 1;
+            }
+            old
+        };
+        if old.is_none() {
+            self.count += 1;
+        }
+        old
+    }
+
+    fn remove(&mut self, id: Position) -> Option<C> {
+        let (cx, cy, cell) = split(id);
+        let index = self.chunk_index(cx, cy)?;
+        let old = {
+            let chunk = self.chunks[index].as_mut()?;
+            let old = chunk.cells[cell].take();
+            if old.is_some() {
+                chunk.count -= 1;
+            }
+            old
+        };
+        if old.is_some() {
+            self.count -= 1;
+        }
+        old
+    }
+
+    fn get_or_else<F: FnOnce() -> C>(&mut self, id: Position, f: F) -> &mut C {
+        if !self.has(id) {
+            self.insert(id, f());
+        }
+        self.get_mut(id).unwrap()
+    }
+
+    fn count(&self) -> usize {
+        self.count
+    }
+
+    fn clear(&mut self) {
+        for chunk in self.chunks.iter_mut().filter_map(Option::as_mut) {
+            if chunk.count > 0 {
+                for cell in &mut chunk.cells {
+                    *cell = None;
+                }
+                chunk.count = 0;
+            }
+        }
+        self.count = 0;
+    }
+}
+
+// Position of the tile at `cell` in the chunk at chunk coordinates (cx, cy)
+fn join(cx: i32, cy: i32, cell: usize) -> Position {
+    let cell = cell as i32;
+    Position {
+        x: (cx << CHUNK_BITS) + cell / CHUNK_SIZE,
+        y: (cy << CHUNK_BITS) + cell % CHUNK_SIZE,
+    }
+}
+
+impl<'a, C: Component> Iterate<'a, Position, C> for GridStorage<Position, C> {
+    type Ids = Box<dyn Iterator<Item=Position> + 'a>;
+    type Iter = Box<dyn Iterator<Item=(Position, &'a C)> + 'a>;
+    type IterMut = Box<dyn Iterator<Item=(Position, &'a mut C)> + 'a>;
+
+    fn ids(&'a self) -> Self::Ids {
+        Box::new(self.iter().map(|(id, _)| id))
+    }
+
+    fn iter(&'a self) -> Self::Iter {
+        Box::new(self.chunks.iter().enumerate()
+            .filter_map(|(index, chunk)| chunk.as_ref().map(|chunk| (index, chunk)))
+            .filter(|&(_, chunk)| chunk.count > 0)
+            .flat_map(move |(index, chunk)| {
+                let (cx, cy) = self.chunk_position(index);
+                chunk.cells.iter().enumerate().filter_map(move |(cell, c)| {
+                    c.as_ref().map(|c| (join(cx, cy, cell), c))
+                })
+            }))
+    }
+
+    fn iter_mut(&'a mut self) -> Self::IterMut {
+        let (min_cx, min_cy, height) = (self.min_cx, self.min_cy, self.height);
+        Box::new(self.chunks.iter_mut().enumerate()
+            .filter_map(|(index, chunk)| chunk.as_mut().map(|chunk| (index, chunk)))
+            .filter(|(_, chunk)| chunk.count > 0)
+            .flat_map(move |(index, chunk)| {
+                let index = index as i32;
+                let (cx, cy) = (min_cx + index / height, min_cy + index % height);
+                chunk.cells.iter_mut().enumerate().filter_map(move |(cell, c)| {
+                    c.as_mut().map(|c| (join(cx, cy, cell), c))
+                })
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Mark(i32);
+
+    impl Component for Mark {}
+
+    fn pos(x: i32, y: i32) -> Position {
+        Position { x, y }
+    }
+
+    #[test]
+    fn negative_coordinates_are_kept_apart() {
+        let mut grid = GridStorage::new();
+        grid.insert(pos(-1, -1), Mark(1));
+        grid.insert(pos(-16, -17), Mark(2));
+        grid.insert(pos(0, 0), Mark(3));
+        assert_eq!(grid.get(pos(-1, -1)), Some(&Mark(1)));
+        assert_eq!(grid.get(pos(-16, -17)), Some(&Mark(2)));
+        assert_eq!(grid.get(pos(0, 0)), Some(&Mark(3)));
+        assert_eq!(grid.get(pos(-1, 0)), None);
+        assert_eq!(grid.get(pos(0, -1)), None);
+        assert_eq!(grid.get(pos(-16, -1)), None);
+        assert_eq!(grid.count(), 3);
+    }
+
+    #[test]
+    fn growing_keeps_what_was_there() {
+        let mut grid = GridStorage::new();
+        grid.insert(pos(0, 0), Mark(1));
+        grid.insert(pos(15, 15), Mark(2));
+        assert_eq!((grid.width, grid.height), (1, 1));
+
+        // One step over each edge of the first chunk
+        grid.insert(pos(16, 0), Mark(3));
+        grid.insert(pos(0, -1), Mark(4));
+        assert_eq!((grid.min_cx, grid.min_cy, grid.width, grid.height), (0, -1, 2, 2));
+        grid.insert(pos(-40, 40), Mark(5));
+        assert_eq!((grid.min_cx, grid.min_cy, grid.width, grid.height), (-3, -1, 5, 4));
+
+        assert_eq!(grid.get(pos(0, 0)), Some(&Mark(1)));
+        assert_eq!(grid.get(pos(15, 15)), Some(&Mark(2)));
+        assert_eq!(grid.get(pos(16, 0)), Some(&Mark(3)));
+        assert_eq!(grid.get(pos(0, -1)), Some(&Mark(4)));
+        assert_eq!(grid.get(pos(-40, 40)), Some(&Mark(5)));
+        assert_eq!(grid.count(), 5);
+        // Chunks are only allocated once something goes in them
+        assert_eq!(grid.chunks.iter().filter(|c| c.is_some()).count(), 4);
+    }
+
+    #[test]
+    fn removing_and_clearing() {
+        let mut grid = GridStorage::new();
+        grid.insert(pos(3, 4), Mark(1));
+        grid.insert(pos(20, 4), Mark(2));
+        assert_eq!(grid.insert(pos(3, 4), Mark(3)), Some(Mark(1)));
+        assert_eq!(grid.count(), 2);
+
+        assert_eq!(grid.remove(pos(3, 4)), Some(Mark(3)));
+        assert_eq!(grid.remove(pos(3, 4)), None);
+        assert_eq!(grid.remove(pos(-100, 4)), None);
+        assert!(!grid.has(pos(3, 4)));
+        assert_eq!(grid.count(), 1);
+
+        grid.insert(pos(5, 5), Mark(4));
+        grid.clear();
+        assert_eq!(grid.count(), 0);
+        assert!(!grid.has(pos(5, 5)));
+        assert!(!grid.has(pos(20, 4)));
+        assert_eq!(grid.iter().count(), 0);
+        // The chunks stay allocated for reuse
+        assert_eq!(grid.chunks.iter().filter(|c| c.is_some()).count(), 2);
+
+        grid.insert(pos(5, 5), Mark(5));
+        assert_eq!(grid.get(pos(5, 5)), Some(&Mark(5)));
+        assert_eq!(grid.count(), 1);
+    }
+
+    #[test]
+    fn iterates_by_chunk_then_by_tile_in_column_order() {
+        let mut grid = GridStorage::new();
+        for (i, &p) in [pos(17, 0), pos(1, 0), pos(0, 20), pos(0, 1), pos(-1, 3), pos(0, 0)].iter().enumerate() {
+            grid.insert(p, Mark(i as i32));
+        }
+        let expected = vec![pos(-1, 3), pos(0, 0), pos(0, 1), pos(1, 0), pos(0, 20), pos(17, 0)];
+        assert_eq!(grid.ids().collect::<Vec<_>>(), expected);
+        assert_eq!(grid.iter().map(|(p, _)| p).collect::<Vec<_>>(), expected);
+        assert_eq!(grid.iter_mut().map(|(p, _)| p).collect::<Vec<_>>(), expected);
+        assert_eq!(grid.iter().map(|(_, m)| m.0).collect::<Vec<_>>(), vec![4, 5, 3, 1, 2, 0]);
+    }
+}
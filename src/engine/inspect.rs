@@ -0,0 +1,60 @@
+use std::any::type_name;
+use std::fmt::Debug;
+
+use super::*;
+
+/// A component or resource, formatted for debugging.
+#[derive(Debug, Clone)]
+pub struct Dump {
+    pub name: &'static str,
+    pub value: String,
+}
+
+impl Dump {
+    fn new<T: Debug>(value: &T) -> Dump {
+        Dump {
+            name: short_type_name::<T>(),
+            value: format!("{:?}", value),
+        }
+    }
+}
+
+/// Just the type's own name, without the module path.
+pub fn short_type_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+struct InspectComponents<I: Id> {
+    id: I,
+    dumps: Vec<Dump>,
+}
+
+impl<S: EntityStorage<I>, I: Id> VisitComponentTypes<S, I> for InspectComponents<I> {
+    fn visit<C: Component>(&mut self, s: &S) where S: EntityComponent<I, C> {
+        if let Some(c) = s.component::<C>().get(self.id) {
+            self.dumps.push(Dump::new(c));
+        }
+    }
+}
+
+struct InspectResources(Vec<Dump>);
+
+impl<S: World> VisitResources<S> for InspectResources {
+    fn visit<R: Resource>(&mut self, s: &S) where S: HasResource<R> {
+        self.0.push(Dump::new(s.resource::<R>()));
+    }
+}
+
+/// Every component `id` has, in the order the world declares them.
+pub fn inspect<S: EntityStorage<I>, I: Id>(world: &S, id: I) -> Vec<Dump> {
+    let mut inspect = InspectComponents { id, dumps: vec![] };
+    world.visit_component_types(&mut inspect);
+    inspect.dumps
+}
+
+pub fn inspect_resources<S: World>(world: &S) -> Vec<Dump> {
+    let mut inspect = InspectResources(vec![]);
+    world.visit_resources(&mut inspect);
+    inspect.0
+}
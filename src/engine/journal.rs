@@ -0,0 +1,79 @@
+/// What happens to changes made through `EntityMut`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CheckGuard {
+    /// Changes are journaled and always rolled back at the end.
+    CheckOnly,
+    /// Changes are journaled until the transaction is committed or rolled
+    /// back.
+    Uncommitted,
+    /// No transaction is open, so changes are permanent.
+    Committed,
+}
+
+/// Marks the start of a transaction so it can be committed or rolled back.
+#[must_use]
+pub struct Savepoint {
+    len: usize,
+    guard: CheckGuard,
+}
+
+pub type Undo<S> = Box<dyn FnOnce(&mut S)>;
+
+/// Undo log for changes made while a transaction is open.
+pub struct Journal<S> {
+    guard: CheckGuard,
+    undo: Vec<Undo<S>>,
+}
+
+impl<S> Default for Journal<S> {
+    fn default() -> Self {
+        Journal {
+            guard: CheckGuard::Committed,
+            undo: Vec::new(),
+        }
+    }
+}
+
+impl<S> Journal<S> {
+    pub fn guard(&self) -> CheckGuard {
+        self.guard
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.guard != CheckGuard::Committed
+    }
+
+    pub fn record(&mut self, undo: Undo<S>) {
+        if self.is_recording() {
+            self.undo.push(undo);
+        }
+    }
+
+    // Transactions can nest. A check-only transaction stays check-only all
+    // the way down, since everything in it will be rolled back anyway.
+    pub fn begin(&mut self, guard: CheckGuard) -> Savepoint {
+        let savepoint = Savepoint {
+            len: self.undo.len(),
+            guard: self.guard,
+        };
+        if self.guard != CheckGuard::CheckOnly {
+            self.guard = guard;
+        }
+        savepoint
+    }
+
+    pub fn commit(&mut self, savepoint: Savepoint) {
+        self.guard = savepoint.guard;
+        if self.guard == CheckGuard::Committed {
+            self.undo.clear();
+        }
+    }
+
+    /// Returns the undo steps in the order they need to be applied.
+    pub fn unwind(&mut self, savepoint: Savepoint) -> Vec<Undo<S>> {
+        self.guard = savepoint.guard;
+        let mut undo = self.undo.split_off(savepoint.len);
+        undo.reverse();
+        undo
+    }
+}
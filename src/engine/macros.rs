@@ -1,10 +1,10 @@
 pub mod hlist {
     use std::marker::PhantomData;
 
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Clone)]
     pub struct Nil;
 
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Clone)]
     pub struct Cons<H, T>(H, T);
 
     pub trait Get<E, V> {
@@ -26,30 +26,127 @@ pub mod hlist {
     pub type ConsHack<H, T, I> = Cons<H, Cons<PhantomData<I>, T>>;
 }
 
+// Components use BTreeStorage unless another storage is named after them,
+// as in `Tile: GridStorage,`. I'm using BTreeMap by default so that
+// iteration order will be predictable.
+macro_rules! component_storage {
+    ($id:ty, $component:ty) => {
+        $crate::engine::BTreeStorage<$id, $component>
+    };
+    ($id:ty, $component:ty, $storage:ident) => {
+        $crate::engine::$storage<$id, $component>
+    };
+}
+
+// Resources are listed in a block after the world's ids, as in
+// `resources { CurrentTurn, }`, and there's one of each per world.
 macro_rules! world {
     (
         $name:ident {
             $($id:ty: {
-                $($component:ty,)*
+                $($component:ty $(: $storage:ident)*,)*
+            })*
+        }
+    ) => {
+        world! {
+            $name {
+                $($id: {
+                    $($component $(: $storage)*,)*
+                })*
+            }
+            resources {}
+        }
+    };
+    (
+        $name:ident {
+            $($id:ty: {
+                $($component:ty $(: $storage:ident)*,)*
             })*
         }
+        resources {
+            $($resource:ty,)*
+        }
     ) => {
-        // I'm using BTreeMap here so that iteration order will be predictable
         struct $name(
+            $($crate::engine::macros::hlist::ConsHack<
+                $crate::engine::IdAllocation<$id>,)*
             $($($crate::engine::macros::hlist::ConsHack<
-                $crate::engine::BTreeStorage<$id, $component>,)*)*
+                component_storage!($id, $component $(, $storage)*),)*)*
                 $crate::engine::macros::hlist::Nil
                 $($(, ($id, $component)>)*)*
+                $(, $id>)*,
+            $($crate::engine::macros::hlist::ConsHack<$resource,)*
+                $crate::engine::macros::hlist::Nil
+                $(, $resource>)*,
+            $crate::engine::Journal<$name>,
+            $crate::engine::Observers<$name>,
         );
 
         impl $crate::engine::World for $name {
             fn new() -> $name {
-                $name(Default::default())
+                $name(Default::default(), Default::default(), Default::default(), Default::default())
+            }
+
+            fn snapshot(&self) -> $name {
+                $name(self.0.clone(), self.1.clone(), Default::default(), self.3.clone())
+            }
+
+            fn journal(&self) -> &$crate::engine::Journal<$name> {
+                &self.2
+            }
+
+            fn journal_mut(&mut self) -> &mut $crate::engine::Journal<$name> {
+                &mut self.2
+            }
+
+            fn observers(&self) -> &$crate::engine::Observers<$name> {
+                &self.3
+            }
+
+            fn observers_mut(&mut self) -> &mut $crate::engine::Observers<$name> {
+                &mut self.3
+            }
+
+            #[allow(unused_variables)]
+            fn visit_resources<V: $crate::engine::VisitResources<Self>>(&self, v: &mut V) {
+                $(
+                    v.visit::<$resource>(self);
+                )*
+            }
+
+            #[allow(unused_variables)]
+            fn visit_resources_mut<V: $crate::engine::VisitResourcesMut<Self>>(&mut self, v: &mut V) {
+                $(
+                    v.visit_mut::<$resource>(self);
+                )*
             }
         }
 
+        $(
+            impl $crate::engine::HasResource<$resource> for $name {
+                fn borrow_resource(&self) -> &$resource {
+                    $crate::engine::macros::hlist::Get::get(&self.1)
+                }
+                fn borrow_resource_mut(&mut self) -> &mut $resource {
+                    $crate::engine::macros::hlist::Get::get_mut(&mut self.1)
+                }
+            }
+        )*
+
         $(
             impl $crate::engine::EntityStorage<$id> for $name {
+                fn allocator(&self) -> &<$id as $crate::engine::Id>::Allocator {
+                    let a: &$crate::engine::IdAllocation<$id> =
+                        $crate::engine::macros::hlist::Get::get(&self.0);
+                    &a.0
+                }
+
+                fn allocator_mut(&mut self) -> &mut <$id as $crate::engine::Id>::Allocator {
+                    let a: &mut $crate::engine::IdAllocation<$id> =
+                        $crate::engine::macros::hlist::Get::get_mut(&mut self.0);
+                    &mut a.0
+                }
+
                 #[allow(unused_variables)]
                 fn visit_component_types<
                     V: $crate::engine::VisitComponentTypes<Self, $id>>(
@@ -73,7 +170,7 @@ macro_rules! world {
 
             $(
                 impl $crate::engine::EntityComponent<$id, $component> for $name {
-                    type Storage = $crate::engine::BTreeStorage<$id, $component>;
+                    type Storage = component_storage!($id, $component $(, $storage)*);
 
                     fn borrow(&self) -> &Self::Storage {
                         $crate::engine::macros::hlist::Get::get(&self.0)
@@ -1,11 +1,35 @@
 #[macro_use]
 pub mod macros;
 
+mod allocator;
+pub use self::allocator::*;
+
+mod journal;
+pub use self::journal::*;
+
+mod observer;
+pub use self::observer::*;
+
 mod btree_storage;
 pub use self::btree_storage::BTreeStorage;
 
+mod grid_storage;
+pub use self::grid_storage::GridStorage;
+
 mod geometry;
 pub use self::geometry::*;
 
 mod world;
 pub use self::world::*;
+
+mod query;
+pub use self::query::*;
+
+mod inspect;
+pub use self::inspect::*;
+
+mod schedule;
+pub use self::schedule::*;
+
+mod diff;
+pub use self::diff::*;
@@ -0,0 +1,257 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use super::*;
+
+/// A change to one id's component, as seen by observers.
+#[derive(Debug, Clone)]
+pub enum ComponentEvent<C> {
+    Inserted(C),
+    Removed(C),
+    Modified { old: C, new: C },
+}
+
+pub type Observer<S, I, C> = fn(&mut S, I, &ComponentEvent<C>);
+
+/// Runs just before an id is despawned, while it still has its components.
+pub type DespawnHook<S, I> = fn(&mut S, I);
+
+/// Handlers to run when a component of some type changes, keyed by id and
+/// component type.
+///
+/// Observers run for changes made through `EntityMut` and for components
+/// removed by `despawn`, including changes that end up rolled back, but not
+/// for the journal undoing them. Any derived data an observer keeps up to
+/// date should be changed through `EntityMut` too, so that it gets rolled
+/// back along with everything else.
+///
+/// Despawn hooks, keyed by id type, are there to fix up anything else that
+/// refers to an id before it goes, like whatever it was holding.
+pub struct Observers<S> {
+    // Each value is an Rc<Vec<Observer<S, I, C>>>
+    handlers: HashMap<(TypeId, TypeId), Rc<dyn Any>>,
+    // Each value is an Rc<Vec<DespawnHook<S, I>>>
+    despawn_hooks: HashMap<TypeId, Rc<dyn Any>>,
+    _world: PhantomData<Observer<S, (), ()>>,
+}
+
+impl<S> Default for Observers<S> {
+    fn default() -> Self {
+        Observers { handlers: HashMap::new(), despawn_hooks: HashMap::new(), _world: PhantomData }
+    }
+}
+
+impl<S> Clone for Observers<S> {
+    fn clone(&self) -> Self {
+        Observers {
+            handlers: self.handlers.clone(),
+            despawn_hooks: self.despawn_hooks.clone(),
+            _world: PhantomData,
+        }
+    }
+}
+
+impl<S: 'static> Observers<S> {
+    fn key<I: Id, C: Component>() -> (TypeId, TypeId) {
+        (TypeId::of::<I>(), TypeId::of::<C>())
+    }
+
+    pub fn add<I: Id, C: Component>(&mut self, f: Observer<S, I, C>) {
+        let mut list = self.get::<I, C>().map(|l| (*l).clone()).unwrap_or_default();
+        list.push(f);
+        self.handlers.insert(Self::key::<I, C>(), Rc::new(list));
+    }
+
+    pub fn has<I: Id, C: Component>(&self) -> bool {
+        self.handlers.contains_key(&Self::key::<I, C>())
+    }
+
+    pub fn get<I: Id, C: Component>(&self) -> Option<Rc<Vec<Observer<S, I, C>>>> {
+        self.handlers.get(&Self::key::<I, C>())
+            .and_then(|list| list.clone().downcast().ok())
+    }
+
+    pub fn add_despawn_hook<I: Id>(&mut self, f: DespawnHook<S, I>) {
+        let mut list = self.despawn_hooks::<I>().map(|l| (*l).clone()).unwrap_or_default();
+        list.push(f);
+        self.despawn_hooks.insert(TypeId::of::<I>(), Rc::new(list));
+    }
+
+    pub fn despawn_hooks<I: Id>(&self) -> Option<Rc<Vec<DespawnHook<S, I>>>> {
+        self.despawn_hooks.get(&TypeId::of::<I>())
+            .and_then(|list| list.clone().downcast().ok())
+    }
+}
+
+// Handlers are free to make further changes, which notify their own
+// observers in turn.
+pub fn notify<S, I, C>(world: &mut S, id: I, event: ComponentEvent<C>)
+    where S: EntityComponent<I, C> + 'static, I: Id, C: Component
+{
+    if let Some(handlers) = world.observers().get::<I, C>() {
+        for handler in handlers.iter() {
+            handler(world, id, &event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+    struct Thing(u32, u32);
+
+    impl Id for Thing {
+        type Allocator = GenerationalAllocator;
+    }
+
+    impl GenerationalId for Thing {
+        fn from_parts(index: u32, generation: u32) -> Thing { Thing(index, generation) }
+        fn index(self) -> u32 { self.0 }
+        fn generation(self) -> u32 { self.1 }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Weight(i32);
+
+    impl Component for Weight {}
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Label(&'static str);
+
+    impl Component for Label {}
+
+    // What the observers have seen, in order
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Seen(Vec<String>);
+
+    impl Resource for Seen {}
+
+    world! {
+        ObservedWorld {
+            Thing: {
+                Weight,
+                Label,
+            }
+            Position: {
+                Weight,
+                Label,
+            }
+        }
+        resources {
+            Seen,
+        }
+    }
+
+    // Bypasses the journal, so that rolling back doesn't hide what was seen
+    fn see(w: &mut ObservedWorld, what: String) {
+        w.borrow_resource_mut().0.push(what);
+    }
+
+    fn seen(w: &mut ObservedWorld) -> Vec<String> {
+        ::std::mem::take(&mut w.borrow_resource_mut().0)
+    }
+
+    fn thing_weight(w: &mut ObservedWorld, _: Thing, event: &ComponentEvent<Weight>) {
+        see(w, format!("thing {:?}", event));
+    }
+
+    fn thing_weight_again(w: &mut ObservedWorld, _: Thing, _: &ComponentEvent<Weight>) {
+        see(w, "thing again".to_string());
+    }
+
+    fn thing_label(w: &mut ObservedWorld, _: Thing, event: &ComponentEvent<Label>) {
+        see(w, format!("thing {:?}", event));
+    }
+
+    fn position_weight(w: &mut ObservedWorld, _: Position, event: &ComponentEvent<Weight>) {
+        see(w, format!("position {:?}", event));
+    }
+
+    fn observed_world() -> ObservedWorld {
+        let mut w = ObservedWorld::new();
+        w.observe(thing_weight);
+        w.observe(thing_weight_again);
+        w.observe(thing_label);
+        w.observe(position_weight);
+        w
+    }
+
+    #[test]
+    fn handlers_are_picked_by_id_type_and_component() {
+        let mut w = observed_world();
+        let thing: Thing = w.spawn();
+        let pos = Position { x: 1, y: 2 };
+
+        w.entity_mut(thing).insert(Weight(1));
+        assert_eq!(seen(&mut w), vec!["thing Inserted(Weight(1))", "thing again"]);
+        w.entity_mut(thing).insert(Label("box"));
+        assert_eq!(seen(&mut w), vec!["thing Inserted(Label(\"box\"))"]);
+        w.entity_mut(pos).insert(Weight(2));
+        assert_eq!(seen(&mut w), vec!["position Inserted(Weight(2))"]);
+        // Nothing observes labels on positions
+        w.entity_mut(pos).insert(Label("floor"));
+        assert!(seen(&mut w).is_empty());
+    }
+
+    #[test]
+    fn handlers_see_inserts_updates_and_removes() {
+        let mut w = observed_world();
+        let pos = Position { x: 0, y: 0 };
+
+        w.entity_mut(pos).insert(Weight(1));
+        w.entity_mut(pos).insert(Weight(2));
+        assert_eq!(w.entity_mut(pos).update(|c: &mut Weight| c.0 += 1), Ok(()));
+        // get_mut goes around the observers
+        w.entity_mut(pos).get_mut::<Weight>().unwrap().0 = 10;
+        assert_eq!(w.entity_mut(pos).remove(), Ok(Weight(10)));
+        assert_eq!(seen(&mut w), vec![
+            "position Inserted(Weight(1))",
+            "position Modified { old: Weight(1), new: Weight(2) }",
+            "position Modified { old: Weight(2), new: Weight(3) }",
+            "position Removed(Weight(10))",
+        ]);
+
+        let thing: Thing = w.spawn();
+        w.entity_mut(thing).insert(Label("crate"));
+        w.entity_mut(thing).insert(Weight(5));
+        seen(&mut w);
+        w.despawn(thing);
+        assert_eq!(seen(&mut w), vec![
+            "thing Removed(Weight(5))",
+            "thing again",
+            "thing Removed(Label(\"crate\"))",
+        ]);
+    }
+
+    #[test]
+    fn rolling_back_notifies_nobody() {
+        let mut w = observed_world();
+        let thing: Thing = w.spawn();
+        let pos = Position { x: 3, y: 3 };
+        w.entity_mut(thing).insert(Weight(1));
+        w.entity_mut(pos).insert(Weight(1));
+        seen(&mut w);
+
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        w.entity_mut(pos).insert(Weight(2));
+        let _ = w.entity_mut(pos).remove::<Weight>();
+        w.entity_mut(pos).insert(Weight(3));
+        w.despawn(thing);
+        assert_eq!(seen(&mut w).len(), 5);
+
+        // The journal puts everything back without going through EntityMut
+        w.rollback(savepoint);
+        assert!(seen(&mut w).is_empty());
+        assert_eq!(w.entity(pos).get(), Ok(&Weight(1)));
+        assert_eq!(w.entity(thing).get(), Ok(&Weight(1)));
+
+        // Nor does copying the world
+        let copy = w.snapshot();
+        assert_eq!(copy.entity(pos).get(), Ok(&Weight(1)));
+        assert!(seen(&mut w).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+use std::marker::PhantomData;
+
+use super::*;
+
+/// One part of a query, looked up for each id being joined.
+///
+/// `&C` requires the component and yields a reference to it, `Option<&C>`
+/// yields it if it's there, and `Not<C>` excludes ids that have it.
+pub trait Fetch<'a, S: EntityStorage<I>, I: Id> {
+    type Item;
+
+    fn fetch(world: &'a S, id: I) -> Option<Self::Item>;
+}
+
+/// A required component whose ids a query can be driven by.
+pub trait Drive<'a, S: EntityStorage<I>, I: Id>: Fetch<'a, S, I> {
+    type Ids: Iterator<Item=I> + 'a;
+
+    fn ids(world: &'a S) -> Self::Ids;
+}
+
+/// A tuple of fetches, the first of which must be a required component.
+pub trait Query<'a, S: EntityStorage<I>, I: Id> {
+    type Item;
+    type Ids: Iterator<Item=I> + 'a;
+
+    fn ids(world: &'a S) -> Self::Ids;
+    fn fetch(world: &'a S, id: I) -> Option<Self::Item>;
+}
+
+/// Excludes ids that have component `C`.
+pub struct Not<C>(PhantomData<C>);
+
+impl<'a, S, I, C> Fetch<'a, S, I> for &C
+    where S: EntityComponent<I, C>, S::Storage: 'a, I: Id, C: Component
+{
+    type Item = &'a C;
+
+    fn fetch(world: &'a S, id: I) -> Option<&'a C> {
+        world.component::<C>().get(id)
+    }
+}
+
+impl<'a, S, I, C> Drive<'a, S, I> for &C
+    where S: EntityComponent<I, C>, S::Storage: 'a, I: Id, C: Component
+{
+    type Ids = <S::Storage as Iterate<'a, I, C>>::Ids;
+
+    fn ids(world: &'a S) -> Self::Ids {
+        world.component::<C>().ids()
+    }
+}
+
+impl<'a, S, I, C> Fetch<'a, S, I> for Option<&C>
+    where S: EntityComponent<I, C>, S::Storage: 'a, I: Id, C: Component
+{
+    type Item = Option<&'a C>;
+
+    fn fetch(world: &'a S, id: I) -> Option<Option<&'a C>> {
+        Some(world.component::<C>().get(id))
+    }
+}
+
+impl<'a, S, I, C> Fetch<'a, S, I> for Not<C>
+    where S: EntityComponent<I, C>, I: Id, C: Component
+{
+    type Item = ();
+
+    fn fetch(world: &'a S, id: I) -> Option<()> {
+        if world.component::<C>().has(id) { None } else { Some(()) }
+    }
+}
+
+macro_rules! impl_query {
+    ($first:ident $(, $rest:ident)*) => {
+        impl<'a, S, I, $first $(, $rest)*> Query<'a, S, I> for ($first, $($rest,)*)
+            where S: EntityStorage<I>, I: Id,
+                  $first: Drive<'a, S, I>
+                  $(, $rest: Fetch<'a, S, I>)*
+        {
+            type Item = ($first::Item, $($rest::Item,)*);
+            type Ids = $first::Ids;
+
+            fn ids(world: &'a S) -> Self::Ids {
+                $first::ids(world)
+            }
+
+            fn fetch(world: &'a S, id: I) -> Option<Self::Item> {
+                Some(($first::fetch(world, id)?, $($rest::fetch(world, id)?,)*))
+            }
+        }
+    };
+}
+
+impl_query!(A);
+impl_query!(A, B);
+impl_query!(A, B, C);
+impl_query!(A, B, C, D);
+impl_query!(A, B, C, D, E);
+impl_query!(A, B, C, D, E, F);
+
+/// Iterates over the ids matching a query, in the order the first
+/// component's storage lists them (id order for `BTreeStorage`).
+pub struct QueryIter<'a, S: EntityStorage<I> + 'a, I: Id, Q: Query<'a, S, I>> {
+    world: &'a S,
+    ids: Q::Ids,
+}
+
+impl<'a, S: EntityStorage<I> + 'a, I: Id, Q: Query<'a, S, I>> QueryIter<'a, S, I, Q> {
+    pub fn new(world: &'a S) -> Self {
+        QueryIter {
+            world,
+            ids: Q::ids(world),
+        }
+    }
+}
+
+impl<'a, S: EntityStorage<I> + 'a, I: Id, Q: Query<'a, S, I>> Iterator for QueryIter<'a, S, I, Q> {
+    type Item = (I, Q::Item);
+
+    fn next(&mut self) -> Option<(I, Q::Item)> {
+        let world = self.world;
+        self.ids.by_ref()
+            .filter(|&id| world.is_live(id))
+            .filter_map(|id| Q::fetch(world, id).map(|item| (id, item)))
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Height(i32);
+
+    impl Component for Height {}
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Lit;
+
+    impl Component for Lit {}
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Wet;
+
+    impl Component for Wet {}
+
+    world! {
+        QueryWorld {
+            Position: {
+                Height,
+                Lit,
+                Wet,
+            }
+        }
+    }
+
+    fn pos(x: i32, y: i32) -> Position {
+        Position { x, y }
+    }
+
+    // A 5x5 patch of ground, lit along one diagonal and wet on every third tile
+    fn patch() -> QueryWorld {
+        let mut w = QueryWorld::new();
+        for y in 0..5 {
+            for x in 0..5 {
+                let mut e = w.entity_mut(pos(x, y));
+                e.insert(Height(x * 10 + y));
+                if x == y {
+                    e.insert(Lit);
+                }
+                if (x + y) % 3 == 0 {
+                    e.insert(Wet);
+                }
+            }
+        }
+        w
+    }
+
+    #[test]
+    fn optional_fetches_match_with_or_without() {
+        let w = patch();
+        let found: Vec<_> = w.query::<(&Lit, Option<&Wet>)>()
+            .map(|(p, (_, wet))| (p, wet.is_some()))
+            .collect();
+        assert_eq!(found, vec![
+            (pos(0, 0), true),
+            (pos(1, 1), false),
+            (pos(2, 2), false),
+            (pos(3, 3), true),
+            (pos(4, 4), false),
+        ]);
+    }
+
+    #[test]
+    fn not_excludes_ids_with_the_component() {
+        let w = patch();
+        let dry_lit: Vec<_> = w.query::<(&Lit, Not<Wet>)>().map(|(p, _)| p).collect();
+        assert_eq!(dry_lit, vec![pos(1, 1), pos(2, 2), pos(4, 4)]);
+
+        let wet_unlit = w.query::<(&Wet, Not<Lit>)>().count();
+        assert_eq!(wet_unlit, w.component::<Wet>().count() - 2);
+    }
+
+    #[test]
+    fn joins_are_driven_by_the_first_component() {
+        let w = patch();
+        // Only the lit ids are visited when Lit comes first...
+        assert_eq!(<(&Lit, &Height, Option<&Wet>)>::ids(&w).count(), 5);
+        assert_eq!(<(&Height, &Lit, Option<&Wet>)>::ids(&w).count(), 25);
+
+        // ...and either way round gives what a naive scan over every height does,
+        // in the same order
+        let naive: Vec<_> = w.component::<Height>().iter()
+            .filter(|&(p, _)| w.component::<Lit>().has(p))
+            .map(|(p, h)| (p, h.clone(), w.component::<Wet>().has(p)))
+            .collect();
+        let small_first: Vec<_> = w.query::<(&Lit, &Height, Option<&Wet>)>()
+            .map(|(p, (_, h, wet))| (p, h.clone(), wet.is_some()))
+            .collect();
+        let large_first: Vec<_> = w.query::<(&Height, &Lit, Option<&Wet>)>()
+            .map(|(p, (h, _, wet))| (p, h.clone(), wet.is_some()))
+            .collect();
+        assert_eq!(small_first, naive);
+        assert_eq!(large_first, naive);
+    }
+}
@@ -0,0 +1,245 @@
+use std::time::{Duration, Instant};
+
+pub type System<T> = fn(&mut T);
+
+struct Entry<T> {
+    name: &'static str,
+    phase: usize,
+    after: Vec<&'static str>,
+    run: System<T>,
+    time: Duration,
+    runs: u32,
+}
+
+impl<T> Clone for Entry<T> {
+    fn clone(&self) -> Self {
+        Entry {
+            name: self.name,
+            phase: self.phase,
+            after: self.after.clone(),
+            run: self.run,
+            time: self.time,
+            runs: self.runs,
+        }
+    }
+}
+
+/// How long a system has taken, summed over every time it ran.
+#[derive(Debug, Clone)]
+pub struct SystemTiming {
+    pub name: &'static str,
+    pub phase: &'static str,
+    pub total: Duration,
+    pub runs: u32,
+}
+
+/// Systems that run every turn, grouped into named phases that run in the
+/// order they were declared. Within a phase, systems run in the order they
+/// were added, except that a system always runs after the systems it's
+/// declared to come after.
+pub struct Schedule<T> {
+    phases: Vec<&'static str>,
+    systems: Vec<Entry<T>>,
+    // Indexes into systems, in the order they run
+    order: Vec<usize>,
+    // Whether every system named in an `after` has been added
+    checked: bool,
+}
+
+impl<T> Default for Schedule<T> {
+    fn default() -> Self {
+        Schedule::new(&[])
+    }
+}
+
+impl<T> Clone for Schedule<T> {
+    fn clone(&self) -> Self {
+        Schedule {
+            phases: self.phases.clone(),
+            systems: self.systems.clone(),
+            order: self.order.clone(),
+            checked: self.checked,
+        }
+    }
+}
+
+impl<T> Schedule<T> {
+    pub fn new(phases: &[&'static str]) -> Self {
+        Schedule {
+            phases: phases.to_vec(),
+            systems: vec![],
+            order: vec![],
+            checked: true,
+        }
+    }
+
+    /// Panics if the phase doesn't exist, the name is already taken, or the
+    /// systems can't be ordered because of `after`. Systems named in `after`
+    /// can be added later, but `run` panics if they never were.
+    pub fn add(&mut self, name: &'static str, phase: &str, after: &[&'static str], run: System<T>) {
+        let phase = self.phases.iter().position(|&p| p == phase)
+            .unwrap_or_else(|| panic!("system {} is in unknown phase {}", name, phase));
+        assert!(self.find(name).is_none(), "system {} was added twice", name);
+        self.systems.push(Entry {
+            name,
+            phase,
+            after: after.to_vec(),
+            run,
+            time: Duration::new(0, 0),
+            runs: 0,
+        });
+        self.order = self.sort();
+        self.checked = false;
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.systems.iter().position(|s| s.name == name)
+    }
+
+    // Systems added later can be depended on by earlier ones, so this
+    // re-sorts everything each time.
+    fn sort(&self) -> Vec<usize> {
+        let mut after: Vec<Vec<usize>> = vec![];
+        for system in &self.systems {
+            after.push(system.after.iter().filter_map(|&name| {
+                // Systems that haven't been added yet are ignored for now
+                let dep = self.find(name)?;
+                assert!(
+                    self.systems[dep].phase <= system.phase,
+                    "system {} can't run after {}, which is in a later phase", system.name, name,
+                );
+                Some(dep)
+            }).collect());
+        }
+
+        let mut by_phase: Vec<usize> = (0..self.systems.len()).collect();
+        by_phase.sort_by_key(|&i| self.systems[i].phase);
+
+        let mut order = vec![];
+        while order.len() < self.systems.len() {
+            let next = by_phase.iter().cloned().find(|&i| {
+                !order.contains(&i) && after[i].iter().all(|dep| order.contains(dep))
+            });
+            match next {
+                Some(i) => order.push(i),
+                None => panic!("systems depend on each other in a cycle"),
+            }
+        }
+        order
+    }
+
+    /// Runs every system once, in order, keeping track of how long each took.
+    pub fn run(&mut self, target: &mut T) {
+        if !self.checked {
+            for system in &self.systems {
+                for &name in &system.after {
+                    assert!(self.find(name).is_some(),
+                            "system {} runs after {}, which was never added", system.name, name);
+                }
+            }
+            self.checked = true;
+        }
+        for &i in &self.order {
+            let start = Instant::now();
+            (self.systems[i].run)(target);
+            let system = &mut self.systems[i];
+            system.time += start.elapsed();
+            system.runs += 1;
+        }
+    }
+
+    pub fn timings(&self) -> Vec<SystemTiming> {
+        self.order.iter().map(|&i| {
+            let system = &self.systems[i];
+            SystemTiming {
+                name: system.name,
+                phase: self.phases[system.phase],
+                total: system.time,
+                runs: system.runs,
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Log = Vec<&'static str>;
+
+    fn a(log: &mut Log) { log.push("a"); }
+    fn b(log: &mut Log) { log.push("b"); }
+    fn c(log: &mut Log) { log.push("c"); }
+    fn d(log: &mut Log) { log.push("d"); }
+
+    fn run(schedule: &mut Schedule<Log>) -> Log {
+        let mut log = vec![];
+        schedule.run(&mut log);
+        log
+    }
+
+    #[test]
+    fn phases_run_in_the_order_declared() {
+        let mut schedule = Schedule::new(&["first", "second", "third"]);
+        schedule.add("c", "third", &[], c);
+        schedule.add("a", "first", &[], a);
+        schedule.add("d", "third", &[], d);
+        schedule.add("b", "second", &[], b);
+        assert_eq!(run(&mut schedule), vec!["a", "b", "c", "d"]);
+
+        let phases: Vec<_> = schedule.timings().iter().map(|t| t.phase).collect();
+        assert_eq!(phases, vec!["first", "second", "third", "third"]);
+        assert!(schedule.timings().iter().all(|t| t.runs == 1));
+    }
+
+    #[test]
+    fn systems_run_after_what_they_depend_on() {
+        let mut schedule = Schedule::new(&["only"]);
+        // "a" depends on systems that are added later
+        schedule.add("a", "only", &["c", "b"], a);
+        schedule.add("b", "only", &[], b);
+        schedule.add("c", "only", &["d"], c);
+        schedule.add("d", "only", &[], d);
+        assert_eq!(run(&mut schedule), vec!["b", "d", "c", "a"]);
+    }
+
+    #[test]
+    fn depending_on_an_earlier_phase_is_allowed() {
+        let mut schedule = Schedule::new(&["early", "late"]);
+        schedule.add("b", "late", &["a"], b);
+        schedule.add("a", "early", &[], a);
+        assert_eq!(run(&mut schedule), vec!["a", "b"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn cycles_panic() {
+        let mut schedule = Schedule::new(&["only"]);
+        schedule.add("a", "only", &["c"], a);
+        schedule.add("b", "only", &["a"], b);
+        schedule.add("c", "only", &["b"], c);
+    }
+
+    #[test]
+    #[should_panic(expected = "system a runs after b, which was never added")]
+    fn depending_on_a_missing_system_panics() {
+        let mut schedule = Schedule::new(&["only"]);
+        schedule.add("a", "only", &["b"], a);
+        run(&mut schedule);
+    }
+
+    #[test]
+    #[should_panic(expected = "which is in a later phase")]
+    fn depending_on_a_later_phase_panics() {
+        let mut schedule = Schedule::new(&["early", "late"]);
+        schedule.add("b", "late", &[], b);
+        schedule.add("a", "early", &["b"], a);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown phase")]
+    fn unknown_phases_panic() {
+        let mut schedule = Schedule::new(&["only"]);
+        schedule.add("a", "other", &[], a);
+    }
+}
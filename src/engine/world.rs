@@ -1,33 +1,118 @@
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+use super::{IdAllocator, Allocate, CheckGuard, Journal, Savepoint, Query, QueryIter};
+use super::{ComponentEvent, DespawnHook, Observer, Observers, notify};
+
 // TODO: switch to Option once ? works with that?
 pub type QueryResult<T> = Result<T, ()>;
 
-pub type ActionResult<T> = Result<T, ActionError>;
+pub type ComponentResult<T> = Result<T, ComponentError>;
 
-#[derive(Eq, PartialEq)]
-pub enum ActionError {
-    InCheckOnlyMode,
-    Failed,
+/// Why a change through `EntityMut` couldn't be made.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ComponentError {
+    /// The handle refers to something that has been despawned.
+    Stale,
+    /// The id doesn't have that component.
+    Missing,
 }
 
-impl From<()> for ActionError {
-    fn from(_: ()) -> ActionError { ActionError::Failed }
-}
+// Clone is needed so the journal can remember old values, Debug so the world
+// can be inspected, and PartialEq so snapshots can be diffed
+pub trait Component: Clone + Debug + PartialEq + 'static {}
 
-pub trait Component {}
+/// Global state that there's exactly one of per world. Resources start out
+/// as their default value.
+pub trait Resource: Clone + Default + Debug + PartialEq + 'static {}
 
-pub trait World {
+pub trait World: Sized + 'static {
     fn new() -> Self;
 
-    // TODO: move this to WorldRef/Mut
-    fn err<T>(&self) -> ActionResult<T> {
-        Err(ActionError::Failed)
+    /// A copy of every component and resource, with an empty journal, so
+    /// it's best taken outside of any transaction.
+    fn snapshot(&self) -> Self;
+
+    fn journal(&self) -> &Journal<Self>;
+    fn journal_mut(&mut self) -> &mut Journal<Self>;
+
+    fn observers(&self) -> &Observers<Self>;
+    fn observers_mut(&mut self) -> &mut Observers<Self>;
+
+    /// Registers `f` to run whenever an `I`'s `C` is inserted, removed or
+    /// modified.
+    fn observe<I: Id, C: Component>(&mut self, f: Observer<Self, I, C>)
+        where Self: EntityComponent<I, C>
+    {
+        self.observers_mut().add(f);
+    }
+
+    /// Registers `f` to run whenever an `I` is about to be despawned.
+    fn on_despawn<I: Id>(&mut self, f: DespawnHook<Self, I>) where Self: EntityStorage<I> {
+        self.observers_mut().add_despawn_hook(f);
+    }
+
+    fn visit_resources<V: VisitResources<Self>>(&self, v: &mut V);
+    fn visit_resources_mut<V: VisitResourcesMut<Self>>(&mut self, v: &mut V);
+
+    fn resource<R: Resource>(&self) -> &R where Self: HasResource<R> {
+        self.borrow_resource()
+    }
+
+    // Like EntityMut, this journals the old value so it can be rolled back.
+    fn resource_mut<R: Resource>(&mut self) -> &mut R where Self: HasResource<R> {
+        if self.journal().is_recording() {
+            let old = self.borrow_resource().clone();
+            self.journal_mut().record(Box::new(move |s: &mut Self| {
+                *s.borrow_resource_mut() = old;
+            }));
+        }
+        self.borrow_resource_mut()
+    }
+
+    // Only changes made through EntityMut (and spawn/despawn) are journaled.
+    // Anything that writes to a storage directly with component_mut has to
+    // happen outside of a transaction.
+    fn begin(&mut self, guard: CheckGuard) -> Savepoint {
+        self.journal_mut().begin(guard)
+    }
+
+    fn commit(&mut self, savepoint: Savepoint) {
+        self.journal_mut().commit(savepoint)
+    }
+
+    fn rollback(&mut self, savepoint: Savepoint) {
+        for undo in self.journal_mut().unwind(savepoint) {
+            undo(self);
+        }
     }
 }
 
-pub trait Id: Copy + Eq {}
+pub trait HasResource<R: Resource>: World {
+    fn borrow_resource(&self) -> &R;
+    fn borrow_resource_mut(&mut self) -> &mut R;
+}
+
+pub trait Id: Copy + Eq + 'static {
+    type Allocator: IdAllocator<Self>;
+}
 
-// TODO: have WorldMut with mutation tracking
 pub trait EntityStorage<I: Id>: World {
+    fn allocator(&self) -> &I::Allocator;
+    fn allocator_mut(&mut self) -> &mut I::Allocator;
+
+    fn is_live(&self, id: I) -> bool {
+        self.allocator().is_live(id)
+    }
+
+    fn spawn(&mut self) -> I where I::Allocator: Allocate<I> {
+        let id = self.allocator_mut().allocate();
+        self.journal_mut().record(Box::new(move |s: &mut Self| {
+            EntityStorage::<I>::allocator_mut(s).free(id);
+        }));
+        id
+    }
+
     fn visit_component_types<V: VisitComponentTypes<Self, I>>(&self, v: &mut V)
         where Self: Sized;
     fn visit_component_types_mut<V: VisitComponentTypesMut<Self, I>>(&mut self, v: &mut V)
@@ -44,25 +129,58 @@ pub trait EntityStorage<I: Id>: World {
         self.borrow_mut()
     }
 
-    fn entity(&self, id: I) -> EntityRef<Self, I> where Self: Sized {
+    fn entity(&self, id: I) -> EntityRef<'_, Self, I> where Self: Sized {
         EntityRef {
             world: self,
-            id: id,
+            id,
         }
     }
 
-    fn entity_mut(&mut self, id: I) -> EntityMut<Self, I> where Self: Sized {
+    fn entity_mut(&mut self, id: I) -> EntityMut<'_, Self, I> where Self: Sized {
         EntityMut {
             world: self,
-            id: id,
-            committed: false,
-            // check_only: false,
+            id,
+        }
+    }
+
+    /// Runs the despawn hooks, then removes every component `id` has and
+    /// frees the id for reuse, returning how many components there were.
+    fn despawn(&mut self, id: I) -> usize where Self: Sized {
+        if !self.is_live(id) {
+            return 0;
         }
+        if let Some(hooks) = self.observers().despawn_hooks::<I>() {
+            for hook in hooks.iter() {
+                hook(self, id);
+            }
+        }
+        let mut despawn = Despawn { id, removed: 0 };
+        self.visit_component_types_mut(&mut despawn);
+        self.allocator_mut().free(id);
+        self.journal_mut().record(Box::new(move |s: &mut Self| {
+            EntityStorage::<I>::allocator_mut(s).revive(id);
+        }));
+        despawn.removed
+    }
+
+    /// Joins components across storages, e.g.
+    /// `query::<(&AiState, &Location, Option<&Damage>, Not<IsPlayer>)>()`.
+    fn query<'a, Q: Query<'a, Self, I>>(&'a self) -> QueryIter<'a, Self, I, Q>
+        where Self: Sized
+    {
+        QueryIter::new(self)
+    }
+
+    /// Every id that has at least one component.
+    fn ids(&self) -> BTreeSet<I> where Self: Sized, I: Ord {
+        let mut ids = CollectIds(BTreeSet::new());
+        self.visit_component_types(&mut ids);
+        ids.0
     }
 }
 
 pub trait EntityComponent<I: Id, C: Component>: EntityStorage<I> {
-    type Storage: ComponentStorage<I, C>;
+    type Storage: ComponentStorage<I, C> + for<'a> Iterate<'a, I, C>;
 
     fn borrow(&self) -> &Self::Storage;
     fn borrow_mut(&mut self) -> &mut Self::Storage;
@@ -86,14 +204,11 @@ pub trait ComponentStorage<I: Id, C: Component> {
 
     fn count(&self) -> usize;
     fn clear(&mut self);
-
-    // TODO: can I re-add these methods with appropriate Iterate bounds to get
-    // better type inference?
-    // fn ids<'a>(&'a self) -> Box<Iterator<Item=I> + 'a>;
-    // fn iter<'a>(&'a self) -> Box<Iterator<Item=(I, &C)> + 'a>;
-    // fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item=(I, &mut C)> + 'a>;
 }
 
+// Iterating over a single storage goes through Iterate, and over several at
+// once through EntityStorage::query.
+
 pub trait Iterate<'a, I, C: 'a> {
     type Ids: Iterator<Item=I> + 'a;
     type Iter: Iterator<Item=(I, &'a C)> + 'a;
@@ -105,6 +220,14 @@ pub trait Iterate<'a, I, C: 'a> {
 }
 
 
+pub trait VisitResources<S: World> {
+    fn visit<R: Resource>(&mut self, s: &S) where S: HasResource<R>;
+}
+
+pub trait VisitResourcesMut<S: World> {
+    fn visit_mut<R: Resource>(&mut self, s: &mut S) where S: HasResource<R>;
+}
+
 pub trait VisitComponentTypes<S: EntityStorage<I>, I: Id> {
     fn visit<C: Component>(&mut self, s: &S) where S: EntityComponent<I, C>;
 }
@@ -114,6 +237,41 @@ pub trait VisitComponentTypesMut<S: EntityStorage<I>, I: Id> {
 }
 
 
+struct Despawn<I: Id> {
+    id: I,
+    removed: usize,
+}
+
+impl<S: EntityStorage<I>, I: Id> VisitComponentTypesMut<S, I> for Despawn<I> {
+    fn visit_mut<C: Component>(&mut self, s: &mut S) where S: EntityComponent<I, C> {
+        if let Some(old) = s.component_mut::<C>().remove(self.id) {
+            self.removed += 1;
+            record_undo(s, self.id, Some(old.clone()));
+            notify(s, self.id, ComponentEvent::Removed(old));
+        }
+    }
+}
+
+// Journals putting `id`'s component C back the way it was.
+fn record_undo<S, I, C>(world: &mut S, id: I, old: Option<C>)
+    where S: EntityComponent<I, C>, I: Id, C: Component
+{
+    world.journal_mut().record(Box::new(move |s: &mut S| {
+        match old {
+            Some(c) => { s.component_mut::<C>().insert(id, c); }
+            None => { s.component_mut::<C>().remove(id); }
+        }
+    }));
+}
+
+struct CollectIds<I: Id + Ord>(BTreeSet<I>);
+
+impl<S: EntityStorage<I>, I: Id + Ord> VisitComponentTypes<S, I> for CollectIds<I> {
+    fn visit<C: Component>(&mut self, s: &S) where S: EntityComponent<I, C> {
+        self.0.extend(s.component::<C>().ids());
+    }
+}
+
 // TODO: add mutation tracking so I can use CheckFailure in places
 #[derive(Copy, Clone)]
 pub struct EntityRef<'a, S: EntityStorage<I> + 'a, I: Id> {
@@ -125,46 +283,49 @@ impl<'a, S: EntityStorage<I> + 'a, I: Id> EntityRef<'a, S, I> {
     pub fn has<C: Component>(&self) -> bool
         where S: EntityComponent<I, C>
     {
-        self.world.component::<C>().has(self.id)
+        self.is_live() && self.world.component::<C>().has(self.id)
     }
 
     pub fn get<C: Component>(&self) -> QueryResult<&'a C>
         where S: EntityComponent<I, C>, S::Storage: 'a
     {
+        if !self.is_live() {
+            return Err(());
+        }
         self.world.component::<C>().get(self.id).ok_or(())
     }
 
+    /// False if this is a stale handle to something that was despawned.
+    pub fn is_live(&self) -> bool {
+        self.world.is_live(self.id)
+    }
+
     pub fn id(&self) -> I {
         self.id
     }
 
     pub fn world(&self) -> &'a S {
-        &self.world
+        self.world
     }
 }
 
-enum CheckGuard {
-    CheckOnly,
-    Uncommitted,
-    Committed,
-}
-
-// TODO: add mutation tracking so I can use CheckFailure in places
 // TODO: make this a trait so check_only is implicit in the type?
 pub struct EntityMut<'a, S: EntityStorage<I> + 'a, I: Id> {
     world: &'a mut S,
-    // TODO: switch to using CheckGuard
-    // check_guard: &'a mut CheckGuard,
-    committed: bool,
     id: I,
 }
 
-// TODO: use check_only to deny mutation
+// Every change goes through the world's journal, so an open transaction can
+// be rolled back.
 impl<'a, S: EntityStorage<I> + 'a, I: Id> EntityMut<'a, S, I> {
     pub fn has<C: Component>(&self) -> bool
         where S: EntityComponent<I, C>
     {
-        self.world.component::<C>().has(self.id)
+        self.as_ref().has::<C>()
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.world.is_live(self.id)
     }
 
     pub fn get<C: Component>(&self) -> QueryResult<&C>
@@ -178,69 +339,266 @@ impl<'a, S: EntityStorage<I> + 'a, I: Id> EntityMut<'a, S, I> {
     }
 
     pub fn world(&self) -> &S {
-        &self.world
+        self.world
     }
 
-    pub fn as_ref(&self) -> EntityRef<S, I> {
+    pub fn as_ref(&self) -> EntityRef<'_, S, I> {
         EntityRef {
             world: self.world,
             id: self.id,
         }
     }
 
-    pub fn get_mut<C: Component>(&mut self) -> ActionResult<&mut C>
+    /// Observers aren't told about changes made through the returned
+    /// reference. Use `update` for components that are observed.
+    pub fn get_mut<C: Component>(&mut self) -> ComponentResult<&mut C>
         where S: EntityComponent<I, C>
     {
-        match self.world.component_mut::<C>().get_mut(self.id) {
-            Some(r) => {
-                // TODO: figure out if there's a way to make commit() work
-                // self.commit();
-                self.committed = true;
-                Ok(r)
+        if !self.is_live() {
+            return Err(ComponentError::Stale);
+        }
+        if self.world.journal().is_recording() {
+            let old = self.world.component::<C>().get(self.id).cloned();
+            if old.is_some() {
+                record_undo(self.world, self.id, old);
             }
-            None => Err(ActionError::Failed),
         }
+        self.world.component_mut::<C>().get_mut(self.id).ok_or(ComponentError::Missing)
     }
 
+    /// Returns the previous value. On a stale handle `c` is dropped, since
+    /// there's nothing left to attach it to.
     pub fn insert<C: Component>(&mut self, c: C) -> Option<C>
         where S: EntityComponent<I, C>
     {
-        self.commit();
-        self.world.component_mut::<C>().insert(self.id, c)
+        if !self.is_live() {
+            return None;
+        }
+        let event = if self.world.observers().has::<I, C>() { Some(c.clone()) } else { None };
+        let old = self.world.component_mut::<C>().insert(self.id, c);
+        if self.world.journal().is_recording() {
+            record_undo(self.world, self.id, old.clone());
+        }
+        if let Some(new) = event {
+            let event = match old.clone() {
+                Some(old) => ComponentEvent::Modified { old, new },
+                None => ComponentEvent::Inserted(new),
+            };
+            notify(self.world, self.id, event);
+        }
+        old
+    }
+
+    /// Changes a component in place. Unlike `get_mut`, this lets observers
+    /// know about the change.
+    pub fn update<C: Component, F: FnOnce(&mut C)>(&mut self, f: F) -> ComponentResult<()>
+        where S: EntityComponent<I, C>
+    {
+        let mut c = self.get::<C>().map_err(|_| {
+            if self.is_live() { ComponentError::Missing } else { ComponentError::Stale }
+        })?.clone();
+        f(&mut c);
+        self.insert(c);
+        Ok(())
     }
 
-    pub fn remove<C: Component>(&mut self) -> ActionResult<C>
+    pub fn remove<C: Component>(&mut self) -> ComponentResult<C>
         where S: EntityComponent<I, C>
     {
+        if !self.is_live() {
+            return Err(ComponentError::Stale);
+        }
         match self.world.component_mut::<C>().remove(self.id) {
             Some(r) => {
-                self.commit();
+                if self.world.journal().is_recording() {
+                    record_undo(self.world, self.id, Some(r.clone()));
+                }
+                if self.world.observers().has::<I, C>() {
+                    notify(self.world, self.id, ComponentEvent::Removed(r.clone()));
+                }
                 Ok(r)
             }
-            None => Err(ActionError::Failed),
+            None => Err(ComponentError::Missing),
         }
     }
 
-    pub fn get_or_else<C: Component, F: FnOnce() -> C>(&mut self, f: F) -> &mut C
+    pub fn get_or_else<C: Component, F: FnOnce() -> C>(&mut self, f: F) -> ComponentResult<&mut C>
         where S: EntityComponent<I, C>
     {
-        self.commit();
-        self.world.component_mut::<C>().get_or_else(self.id, f)
+        if !self.is_live() {
+            return Err(ComponentError::Stale);
+        }
+        if self.world.journal().is_recording() {
+            let old = self.world.component::<C>().get(self.id).cloned();
+            record_undo(self.world, self.id, old);
+        }
+        Ok(self.world.component_mut::<C>().get_or_else(self.id, f))
     }
 
-    pub fn get_or_default<C: Component + Default>(&mut self) -> &mut C
+    pub fn get_or_default<C: Component + Default>(&mut self) -> ComponentResult<&mut C>
         where S: EntityComponent<I, C>
     {
-        self.commit();
-        self.world.component_mut::<C>().get_or_default(self.id)
+        self.get_or_else(Default::default)
     }
+}
 
-    // TODO: return Result once we're using check_only mode
-    pub fn commit(&mut self) {
-        self.committed = true;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::{GenerationalAllocator, GenerationalId};
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+    struct Thing(u32, u32);
+
+    impl Id for Thing {
+        type Allocator = GenerationalAllocator;
+    }
+
+    impl GenerationalId for Thing {
+        fn from_parts(index: u32, generation: u32) -> Thing { Thing(index, generation) }
+        fn index(self) -> u32 { self.0 }
+        fn generation(self) -> u32 { self.1 }
     }
 
-    // pub fn world_mut(&mut self) -> &mut S {
-    //     &mut self.world
-    // }
+    #[derive(Debug, Clone, PartialEq)]
+    struct Name(&'static str);
+
+    impl Component for Name {}
+
+    // What a thing is being carried in
+    #[derive(Debug, Clone, PartialEq)]
+    struct Inside(Thing);
+
+    impl Component for Inside {}
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Counter(i32);
+
+    impl Resource for Counter {}
+
+    world! {
+        TestWorld {
+            Thing: {
+                Name,
+                Inside,
+            }
+        }
+        resources {
+            Counter,
+        }
+    }
+
+    fn world_with_thing(name: &'static str) -> (TestWorld, Thing) {
+        let mut w = TestWorld::new();
+        let id = w.spawn();
+        w.entity_mut(id).insert(Name(name));
+        (w, id)
+    }
+
+    #[test]
+    fn insert_on_stale_handle_does_nothing() {
+        let (mut w, id) = world_with_thing("old");
+        w.despawn(id);
+
+        assert_eq!(w.entity_mut(id).insert(Name("new")), None);
+        assert!(!w.component::<Name>().has(id));
+    }
+
+    #[test]
+    fn rollback_undoes_insert() {
+        let (mut w, id) = world_with_thing("old");
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        w.entity_mut(id).insert(Name("new"));
+        w.rollback(savepoint);
+        assert_eq!(w.entity(id).get(), Ok(&Name("old")));
+
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        let other: Thing = w.spawn();
+        w.entity_mut(other).insert(Name("other"));
+        w.rollback(savepoint);
+        assert!(!w.is_live(other));
+        assert!(!w.component::<Name>().has(other));
+    }
+
+    #[test]
+    fn rollback_undoes_remove() {
+        let (mut w, id) = world_with_thing("old");
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        assert_eq!(w.entity_mut(id).remove::<Name>(), Ok(Name("old")));
+        w.rollback(savepoint);
+        assert_eq!(w.entity(id).get(), Ok(&Name("old")));
+    }
+
+    #[test]
+    fn rollback_undoes_despawn() {
+        let (mut w, id) = world_with_thing("old");
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        assert_eq!(w.despawn(id), 1);
+        w.rollback(savepoint);
+        assert!(w.is_live(id));
+        assert_eq!(w.entity(id).get(), Ok(&Name("old")));
+    }
+
+    #[test]
+    fn rollback_undoes_resource_changes() {
+        let mut w = TestWorld::new();
+        w.resource_mut::<Counter>().0 = 1;
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        w.resource_mut::<Counter>().0 += 1;
+        w.resource_mut::<Counter>().0 += 1;
+        w.rollback(savepoint);
+        assert_eq!(w.resource::<Counter>(), &Counter(1));
+    }
+
+    #[test]
+    fn commit_keeps_changes() {
+        let (mut w, id) = world_with_thing("old");
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        w.entity_mut(id).insert(Name("new"));
+        w.resource_mut::<Counter>().0 = 2;
+        w.commit(savepoint);
+        assert_eq!(w.entity(id).get(), Ok(&Name("new")));
+        assert_eq!(w.resource::<Counter>(), &Counter(2));
+        assert!(!w.journal().is_recording());
+    }
+
+    #[test]
+    fn check_only_stays_check_only_when_nested() {
+        let (mut w, id) = world_with_thing("old");
+        let outer = w.begin(CheckGuard::CheckOnly);
+        let inner = w.begin(CheckGuard::Uncommitted);
+        w.entity_mut(id).insert(Name("new"));
+        w.commit(inner);
+        w.rollback(outer);
+        assert_eq!(w.entity(id).get(), Ok(&Name("old")));
+    }
+
+    // Takes everything out of a container that's going away
+    fn empty_container(w: &mut TestWorld, id: Thing) {
+        let inside: Vec<Thing> = w.component::<Inside>().iter()
+            .filter(|&(_, inside)| inside.0 == id)
+            .map(|(item, _)| item)
+            .collect();
+        for item in inside {
+            let _ = w.entity_mut(item).remove::<Inside>();
+        }
+    }
+
+    #[test]
+    fn despawn_hooks_fix_up_references() {
+        let (mut w, bag) = world_with_thing("bag");
+        let coin = w.spawn();
+        w.entity_mut(coin).insert(Name("coin"));
+        w.entity_mut(coin).insert(Inside(bag));
+        w.on_despawn(empty_container);
+
+        let savepoint = w.begin(CheckGuard::Uncommitted);
+        assert_eq!(w.despawn(bag), 1);
+        assert!(w.is_live(coin));
+        assert_eq!(w.entity(coin).get::<Inside>(), Err(()));
+        assert_eq!(w.entity(coin).get(), Ok(&Name("coin")));
+
+        w.rollback(savepoint);
+        assert_eq!(w.entity(coin).get(), Ok(&Inside(bag)));
+    }
 }
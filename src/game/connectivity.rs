@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use rand::Rng;
+
+use super::*;
+
+// Paths that would take clearing more tiles than this aren't worth it, and
+// the loot is moved instead
+const MAX_CARVE: u32 = 8;
+
+/// How well connected the most recently generated level turned out, and what
+/// was done to fix it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectivityReport {
+    /// Tiles the player can walk to from where they start.
+    pub reachable_tiles: usize,
+    pub paths_carved: u32,
+    /// Trees cleared, deep water turned into fords, and boulders and walls
+    /// knocked down along those paths.
+    pub tiles_carved: u32,
+    /// Piles of loot that were moved somewhere reachable instead.
+    pub piles_moved: u32,
+    pub diamonds: u32,
+    /// Diamonds that couldn't be reached before anything was done about it.
+    pub stranded_diamonds: u32,
+    pub reachable_diamonds: u32,
+}
+
+impl Resource for ConnectivityReport {}
+
+/// Walkable tiles that can be reached from `start` one step at a time.
+pub fn flood_fill(g: &Game, start: Position) -> HashSet<Position> {
+    let mut reached = HashSet::new();
+    let mut queue = VecDeque::new();
+    if g.get_tile(start).is_walkable() {
+        reached.insert(start);
+        queue.push_back(start);
+    }
+    while let Some(pos) = queue.pop_front() {
+        for &dir in &ALL_DIRECTIONS {
+            let next = pos.step(dir);
+            if g.get_tile(next).is_walkable() && reached.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    reached
+}
+
+// Trees can be cut down, deep water can be forded and boulders can be broken
+// up, but there's nothing beyond the edge of the map and no way through rock
+fn carve_cost(tile: Tile) -> Option<u32> {
+    if tile.is_walkable() {
+        Some(0)
+    } else if [Tile::Tree, Tile::DeepWater, Tile::Boulder, Tile::StoneWall].contains(&tile) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+// The cheapest path from anywhere reachable into `region`, found with a
+// breadth first search that puts free steps at the front of the queue.
+fn cheapest_path(g: &Game, reachable: &HashSet<Position>, region: &HashSet<Position>)
+    -> Option<(u32, Vec<Position>)>
+{
+    let mut cost: HashMap<Position, u32> = reachable.iter().map(|&pos| (pos, 0)).collect();
+    let mut came_from = HashMap::new();
+    // Sorted so that ties are broken the same way every time
+    let mut starts: Vec<Position> = reachable.iter().cloned().collect();
+    starts.sort();
+    let mut queue: VecDeque<Position> = starts.into_iter().collect();
+    while let Some(pos) = queue.pop_front() {
+        let pos_cost = cost[&pos];
+        if region.contains(&pos) {
+            let mut path = vec![pos];
+            while let Some(&prev) = came_from.get(path.last().unwrap()) {
+                path.push(prev);
+            }
+            return Some((pos_cost, path));
+        }
+        for &dir in &ALL_DIRECTIONS {
+            let next = pos.step(dir);
+            if let Some(step_cost) = carve_cost(g.get_tile(next)) {
+                let next_cost = pos_cost + step_cost;
+                if cost.get(&next).is_none_or(|&c| next_cost < c) {
+                    cost.insert(next, next_cost);
+                    came_from.insert(next, pos);
+                    if step_cost == 0 {
+                        queue.push_front(next);
+                    } else {
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+fn carve(g: &mut Game, path: &[Position]) -> u32 {
+    let mut carved = 0;
+    for &pos in path {
+        let new_tile = match g.get_tile(pos) {
+            Tile::Tree => Tile::Ground,
+            Tile::DeepWater => Tile::ShallowWater,
+            Tile::Boulder | Tile::StoneWall => Tile::Rubble,
+            _ => continue,
+        };
+        g.world.entity_mut(pos).insert(new_tile);
+        carved += 1;
+    }
+    carved
+}
+
+fn items_at(g: &Game, pos: Position) -> Vec<Entity> {
+    g.world.entity(pos).get::<Contents>()
+        .map(|c| c.0.iter().cloned().filter(|&id| !g.is_live_actor(id)).collect())
+        .unwrap_or_default()
+}
+
+fn is_target(g: &Game, pos: Position) -> bool {
+    g.get_tile(pos) == Tile::StairsDown || !items_at(g, pos).is_empty()
+}
+
+/// Makes sure the player can get from `start` to the stairs down and every
+/// item on the map, by clearing paths or moving loot, and reports on how it
+/// went.
+pub fn connect_level(g: &mut Game, start: Position) -> ConnectivityReport {
+    let mut report = ConnectivityReport::default();
+    let targets: Vec<Position> = g.world.component::<Tile>().ids()
+        .filter(|&pos| is_target(g, pos))
+        .collect();
+
+    let mut reachable = flood_fill(g, start);
+    report.stranded_diamonds = count_diamonds(g, &reachable).1;
+    for target in targets {
+        if reachable.contains(&target) || !is_target(g, target) {
+            continue;
+        }
+        let mut region = flood_fill(g, target);
+        // Anything lying on a tree or in deep water is in a region of its own
+        region.insert(target);
+        let must_connect = region.iter().any(|&pos| g.get_tile(pos) == Tile::StairsDown);
+        match cheapest_path(g, &reachable, &region) {
+            Some((cost, path)) if cost <= MAX_CARVE || must_connect => {
+                report.tiles_carved += carve(g, &path);
+                report.paths_carved += 1;
+                reachable = flood_fill(g, start);
+            }
+            _ => {
+                report.piles_moved += move_loot(g, &reachable, &region);
+            }
+        }
+    }
+
+    report.reachable_tiles = reachable.len();
+    let (diamonds, stranded) = count_diamonds(g, &reachable);
+    report.diamonds = diamonds;
+    report.reachable_diamonds = diamonds - stranded;
+    report
+}
+
+// All the diamonds lying on the map, and how many of them can't be reached
+fn count_diamonds(g: &Game, reachable: &HashSet<Position>) -> (u32, u32) {
+    let (mut diamonds, mut stranded) = (0, 0);
+    for (_, (&t, &location)) in g.world.query::<(&EntityType, &Location)>() {
+        if let (EntityType::Diamond, Location::Position(pos)) = (t, location) {
+            diamonds += 1;
+            if !reachable.contains(&pos) {
+                stranded += 1;
+            }
+        }
+    }
+    (diamonds, stranded)
+}
+
+// Each pile is moved as a whole onto reachable land
+fn move_loot(g: &mut Game, reachable: &HashSet<Position>, region: &HashSet<Position>) -> u32 {
+    let mut land: Vec<Position> = reachable.iter().cloned()
+        .filter(|&pos| [
+            Tile::Ground, Tile::BoringGround, Tile::ShortGrass, Tile::LongGrass,
+        ].contains(&g.get_tile(pos)))
+        .collect();
+    if land.is_empty() {
+        return 0;
+    }
+    // HashSet order isn't the same from one run to the next
+    land.sort();
+    let mut piles: Vec<Position> = region.iter().cloned().filter(|&pos| !items_at(g, pos).is_empty()).collect();
+    piles.sort();
+
+    for &pile in &piles {
+        let &new_pos = g.rand().choose(&land).unwrap();
+        for id in items_at(g, pile) {
+            g.world.set_location(id, Location::Position(new_pos));
+        }
+    }
+    piles.len() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::tests::{field, CENTER};
+
+    // Surrounds the player with `tile`, from `inner` to `outer` steps out
+    fn wall_in(g: &mut Game, tile: Tile, inner: i32, outer: i32) {
+        for radius in inner..=outer {
+            for pos in CENTER.ring(radius) {
+                g.world.entity_mut(pos).insert(tile);
+            }
+        }
+    }
+
+    fn diamond_position(g: &Game) -> Position {
+        g.world.query::<(&EntityType, &Location)>()
+            .filter_map(|(_, (&t, &location))| match (t, location) {
+                (EntityType::Diamond, Location::Position(pos)) => Some(pos),
+                _ => None,
+            })
+            .next().unwrap()
+    }
+
+    #[test]
+    fn carves_through_thin_walls() {
+        let mut g = field(5);
+        wall_in(&mut g, Tile::Tree, 2, 2);
+        let outside = Position { x: 4, y: 4 };
+        let reachable = flood_fill(&g, CENTER);
+        assert_eq!(reachable.len(), 9);
+        assert!(!reachable.contains(&outside));
+
+        let (cost, path) = cheapest_path(&g, &reachable, &flood_fill(&g, outside)).unwrap();
+        assert_eq!(cost, 1);
+        assert_eq!(carve(&mut g, &path), 1);
+        assert!(flood_fill(&g, CENTER).contains(&outside));
+    }
+
+    #[test]
+    fn connects_loot_behind_thin_walls() {
+        let mut g = field(5);
+        wall_in(&mut g, Tile::StoneWall, 2, 3);
+        let outside = Position { x: 5, y: -5 };
+        g.put_entity(EntityType::Diamond, outside);
+
+        let report = connect_level(&mut g, CENTER);
+        assert_eq!((report.paths_carved, report.tiles_carved, report.piles_moved), (1, 2, 0));
+        assert_eq!((report.diamonds, report.stranded_diamonds, report.reachable_diamonds), (1, 1, 1));
+        assert_eq!(diamond_position(&g), outside);
+        assert!(flood_fill(&g, CENTER).contains(&outside));
+    }
+
+    #[test]
+    fn moves_loot_behind_thick_walls() {
+        let thickness = MAX_CARVE as i32 + 1;
+        let mut g = field(thickness + 3);
+        wall_in(&mut g, Tile::StoneWall, 2, thickness + 1);
+        let outside = Position { x: thickness + 3, y: 0 };
+        g.put_entity(EntityType::Diamond, outside);
+
+        let report = connect_level(&mut g, CENTER);
+        assert_eq!((report.paths_carved, report.tiles_carved, report.piles_moved), (0, 0, 1));
+        assert_eq!(report.reachable_diamonds, 1);
+        assert_eq!(flood_fill(&g, CENTER).len(), 9);
+        assert!(CENTER.chebyshev_distance(diamond_position(&g)) <= 1);
+    }
+}
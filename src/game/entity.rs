@@ -1,3 +1,5 @@
+use std::collections::{HashMap, VecDeque};
+
 use super::*;
 
 pub enum EntityClass {
@@ -30,17 +32,11 @@ pub struct EntityData {
 
 impl EntityData {
     pub fn is_item(&self) -> bool {
-        match self.class {
-            EntityClass::Item { .. } => true,
-            _ => false,
-        }
+        matches!(self.class, EntityClass::Item { .. })
     }
 
     pub fn is_actor(&self) -> bool {
-        match self.class {
-            EntityClass::Actor { .. } => true,
-            _ => false,
-        }
+        matches!(self.class, EntityClass::Actor { .. })
     }
 }
 
@@ -50,6 +46,12 @@ macro_rules! entity_data {
         pub enum EntityType { $($name,)* }
 
         impl EntityType {
+            /// Looks a type up by its variant name, ignoring case.
+            pub fn from_name(name: &str) -> Option<EntityType> {
+                [$(EntityType::$name,)*].iter().cloned()
+                    .find(|t| format!("{:?}", t).eq_ignore_ascii_case(name))
+            }
+
             pub fn data(&self) -> &'static EntityData {
                 match self { $(
                     &EntityType::$name => {
@@ -105,6 +107,14 @@ entity_data! {
             display_priority: 5,
         },
     }
+    Raft: { // crosses deep water
+        name: "raft",
+        ch: '&',
+        color: Some(Color::Yellow),
+        class: EntityClass::Item {
+            display_priority: 4,
+        },
+    }
     Sword: { // melee weapon
         name: "sword",
         ch: '|',
@@ -227,6 +237,7 @@ entity_data! {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Corpse {
     pub turn_created: i32,
     pub original_type: EntityType,
@@ -234,7 +245,7 @@ pub struct Corpse {
 
 impl Component for Corpse {}
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum AiState {
     Waiting,
     Wandering(Position),
@@ -244,40 +255,93 @@ pub enum AiState {
 
 impl Component for AiState {}
 
+/// The turn a creature can act again, after stepping somewhere slow to wade
+/// through.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BusyUntil(pub i32);
+
+impl Component for BusyUntil {}
+
 fn step_towards(g: &mut Game, actor: Entity, pos: Position) -> Position {
     if let Ok(actor_pos) = g.entity_position(actor) {
         if actor_pos != pos {
-            let x_offset = pos.x - actor_pos.x;
-            let y_offset = pos.y - actor_pos.y;
-            let abs_x_offset = x_offset.abs();
-            let abs_y_offset = y_offset.abs();
+            let offset = pos - actor_pos;
+            let step = offset.signum();
+            let (abs_x_offset, abs_y_offset) = (offset.x.abs(), offset.y.abs());
             if abs_y_offset >= abs_x_offset {
-                if g.rand.gen_range(0, abs_y_offset) >= abs_x_offset {
-                    return Position { x: actor_pos.x, y: actor_pos.y + y_offset.signum() };
+                if g.rand().gen_range(0, abs_y_offset) >= abs_x_offset {
+                    return actor_pos + Offset { x: 0, y: step.y };
                 }
             } else {
-                if g.rand.gen_range(0, abs_x_offset) >= abs_y_offset {
-                    return Position { x: actor_pos.x + x_offset.signum(), y: actor_pos.y };
+                if g.rand().gen_range(0, abs_x_offset) >= abs_y_offset {
+                    return actor_pos + Offset { x: step.x, y: 0 };
                 }
             }
-            return Position { x: actor_pos.x + x_offset.signum(), y: actor_pos.y + y_offset.signum() };
+            return actor_pos + step;
         }
     }
     // TODO: return Err instead?
     pos
 }
 
+// How many tiles to search before giving up on finding a way around
+const MAX_DETOUR: usize = 1000;
+
+// Heading straight for something works fine until there's deep water in the
+// way. Then it's worth looking for the way around, or a bridge or ford over it.
+// Getting next to `to` is enough, since it might be out on the water itself.
+fn detour_towards(g: &Game, from: Position, to: Position) -> Option<Position> {
+    // Nowhere to stand next to it, so there's no point looking
+    if !to.ring(1).any(|pos| g.get_tile(pos).is_walkable()) {
+        return None;
+    }
+    let mut came_from = HashMap::new();
+    let mut queue = VecDeque::new();
+    came_from.insert(from, from);
+    queue.push_back(from);
+    while let Some(pos) = queue.pop_front() {
+        if is_adjacent(pos, to) {
+            let mut step = pos;
+            while came_from[&step] != from {
+                step = came_from[&step];
+            }
+            return Some(step);
+        }
+        if came_from.len() > MAX_DETOUR {
+            break;
+        }
+        for &dir in &ALL_DIRECTIONS {
+            let next = pos.step(dir);
+            if g.get_tile(next).is_walkable() && !came_from.contains_key(&next) {
+                came_from.insert(next, pos);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
 // TODO: dedup with Game::move_entity
 // TODO: return Result
 fn move_towards(g: &mut Game, actor: Entity, pos: Position) -> bool {
     // TODO: allow running if sufficient stamina
     let actor_pos = g.entity_position(actor);
-    let new_pos = step_towards(g, actor, pos);
+    let mut new_pos = step_towards(g, actor, pos);
+    if g.get_tile(new_pos) == Tile::DeepWater {
+        if let Some(step) = actor_pos.ok().and_then(|from| detour_towards(g, from, pos)) {
+            new_pos = step;
+        }
+    }
     if actor_pos == Ok(new_pos) { return false; }
     if g.get_tile(new_pos).is_walkable() {
         let target = g.get_actor_by_position(new_pos);
         if target.is_err() {
             g.world.set_location(actor, Location::Position(new_pos));
+            let move_time = g.get_tile(new_pos).move_time() as i32;
+            if move_time > 1 {
+                let until = g.current_turn() + move_time;
+                g.world.entity_mut(actor).insert(BusyUntil(until));
+            }
             return true;
         }
     }
@@ -287,7 +351,7 @@ fn move_towards(g: &mut Game, actor: Entity, pos: Position) -> bool {
 fn move_randomly(g: &mut Game, actor: Entity) {
     if let Ok(actor_pos) = g.entity_position(actor) {
         for _ in 0..8 {
-            let &dir = g.rand.choose(&ALL_DIRECTIONS).unwrap();
+            let &dir = g.rand().choose(&ALL_DIRECTIONS).unwrap();
             let moved = move_towards(g, actor, actor_pos.step(dir));
             if moved {
                 return;
@@ -302,10 +366,12 @@ impl AiState {
         let actor_pos = g.entity_position(actor);
         if let (Some(actor_type), Ok(actor_pos)) = (actor_type, actor_pos) {
             if let EntityClass::Actor { fov_range, smelling, ai: Some(ref ai), .. } = actor_type.data().class {
+                // Sight works both ways, so the player's view says whether
+                // the player is in sight
                 let player = (|| {
                     let distance = g.world.entity(actor_pos).get::<IsVisible>().map(|v| v.0)?;
                     if distance <= fov_range {
-                        Ok((g.player()?, g.player_position()?))
+                        g.nearest(actor_pos, fov_range as i32, |t| t == EntityType::Player).ok_or(())
                     } else {
                         Err(())
                     }
@@ -321,10 +387,8 @@ impl AiState {
                         }
                     }
                     AiState::Fleeing(id, pos) => {
-                        let moved = move_towards(g, actor, Position {
-                            x: actor_pos.x*2 - pos.x,
-                            y: actor_pos.y*2 - pos.y,
-                        });
+                        // Directly away from whatever it's fleeing
+                        let moved = move_towards(g, actor, actor_pos + (actor_pos - pos));
                         if !moved {
                             move_randomly(g, actor);
                         } else {
@@ -339,11 +403,15 @@ impl AiState {
                         }
                         return self;
                     }
+                    AiState::Hunting(id, _) if !g.is_live_actor(id) => {
+                        // The target died, or the handle is stale
+                        self = AiState::Waiting;
+                    }
                     AiState::Hunting(id, pos) => {
                         if let Some(Location::Position(target_pos)) =
                             g.world.entity(id).get().ok().cloned()
                         {
-                            if actor_pos.distance_sq(target_pos) < 4 {
+                            if is_adjacent(actor_pos, target_pos) {
                                 // TODO: if stamina is at 0, do something else
                                 // TODO: really ignore Result?
                                 let _ = g.bump_attack(actor, id);
@@ -369,11 +437,11 @@ impl AiState {
                     }
                 }
 
-                if let Some(&smell) = g.smell_strength.get(&actor_pos) {
+                if let Ok(&Smell(smell)) = g.world.entity(actor_pos).get() {
                     if smell <= smelling {
                         if let Some(dir) = (0..2).filter_map(|_| {
-                            let &dir = g.rand.choose(&ALL_DIRECTIONS).unwrap();
-                            if let Some(&dir_smell) = g.smell_strength.get(&actor_pos.step(dir)) {
+                            let &dir = g.rand().choose(&ALL_DIRECTIONS).unwrap();
+                            if let Ok(&Smell(dir_smell)) = g.world.entity(actor_pos.step(dir)).get() {
                                 if dir_smell < smell {
                                     return Some(dir);
                                 }
@@ -392,11 +460,9 @@ impl AiState {
 
                 // if ai.wanders {
                     if let AiState::Waiting = self {
-                        return AiState::Wandering(Position {
-                            x: actor_pos.x + g.rand.gen_range(0, 2*fov_range)
-                                - g.rand.gen_range(0, 2*fov_range),
-                            y: actor_pos.y + g.rand.gen_range(0, 2*fov_range)
-                                - g.rand.gen_range(0, 2*fov_range),
+                        return AiState::Wandering(actor_pos + Offset {
+                            x: g.rand().gen_range(0, 2*fov_range) - g.rand().gen_range(0, 2*fov_range),
+                            y: g.rand().gen_range(0, 2*fov_range) - g.rand().gen_range(0, 2*fov_range),
                         });
                     }
                 // }
@@ -407,3 +473,84 @@ impl AiState {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::tests::{field, give, CENTER};
+
+    #[test]
+    fn wading_into_a_bog_takes_two_turns() {
+        let mut g = field(4);
+        let (start, bog) = (Position { x: 3, y: 0 }, Position { x: 2, y: 0 });
+        g.world.entity_mut(bog).insert(Tile::Bog);
+        g.put_entity(EntityType::Deer, start);
+        let deer = g.get_actor_by_position(start).unwrap();
+
+        assert!(move_towards(&mut g, deer, bog));
+        let turn = g.current_turn();
+        assert_eq!(g.world.entity(deer).get(), Ok(&BusyUntil(turn + 2)));
+
+        // Stuck on the next turn, then free to go on the one after
+        let away = AiState::Wandering(Position { x: 2, y: 4 });
+        for &(passed, expected) in &[(1, bog), (2, Position { x: 2, y: 1 })] {
+            g.world.resource_mut::<CurrentTurn>().0 = turn + passed;
+            g.world.entity_mut(deer).insert(away);
+            g.run_ai();
+            assert_eq!(g.entity_position(deer), Ok(expected));
+        }
+    }
+
+    // The player out on a raft, with the shore `shore` steps east and the
+    // wolf hunting them from `wolf_pos`
+    fn raft_and_wolf(g: &mut Game, shore: i32, wolf_pos: Position) -> Entity {
+        let water: Vec<_> = g.world.component::<Tile>().ids().filter(|p| p.x < shore).collect();
+        for pos in water {
+            g.world.entity_mut(pos).insert(Tile::DeepWater);
+        }
+        give(g, EntityType::Raft);
+        g.put_entity(EntityType::Wolf, wolf_pos);
+        let wolf = g.get_actor_by_position(wolf_pos).unwrap();
+        let player = g.player().unwrap();
+        g.world.entity_mut(wolf).insert(AiState::Hunting(player, CENTER));
+        update_fov(g);
+        wolf
+    }
+
+    fn player_damage(g: &Game) -> i8 {
+        g.world.entity(g.player().unwrap()).get::<Damage>().map(|d| d.0).unwrap_or(0)
+    }
+
+    #[test]
+    fn bites_rafts_from_the_shore() {
+        let mut g = field(5);
+        raft_and_wolf(&mut g, 1, Position { x: 1, y: 1 });
+        g.run_ai();
+        assert_eq!(player_damage(&g), 2);
+    }
+
+    #[test]
+    fn goes_around_water_to_reach_rafts() {
+        let mut g = field(6);
+        // An inlet between the wolf and the player
+        let inlet = Rect { min_x: 2, max_x: 4, min_y: -1, max_y: 1 };
+        for pos in inlet.positions() {
+            g.world.entity_mut(pos).insert(Tile::DeepWater);
+        }
+        let wolf = raft_and_wolf(&mut g, 1, Position { x: 5, y: 0 });
+        for _ in 0..8 {
+            g.run_ai();
+            update_fov(&mut g);
+        }
+        assert!(is_adjacent(g.entity_position(wolf).unwrap(), CENTER));
+        assert!(player_damage(&g) > 0);
+    }
+
+    #[test]
+    fn gives_up_on_rafts_out_of_reach() {
+        let mut g = field(5);
+        let wolf_pos = Position { x: 3, y: 0 };
+        raft_and_wolf(&mut g, 2, wolf_pos);
+        assert_eq!(detour_towards(&g, wolf_pos, CENTER), None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use ::engine::*;
+use super::{Entity, EntityType};
+
+pub type ActionResult<T> = Result<T, ActionError>;
+
+/// Why an action couldn't be taken.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ActionError {
+    /// The player has left the map, or was never placed on it.
+    NoPlayer,
+    PlayerDead,
+    /// The entity isn't standing anywhere on the map.
+    Unplaced(Entity),
+    Blocked(Position),
+    FullHealth,
+    MissingItem(EntityType),
+    AlreadyRecalling,
+    NoCorpseHere,
+    NoStairsDown,
+    NoStairsUp,
+    TargetIsSelf,
+    OutOfSight(Position),
+    NotAnActor(Entity),
+    /// Creatures don't attack each other, and neither does the player.
+    FriendlyTarget(Entity),
+    Component(ComponentError),
+}
+
+impl From<ComponentError> for ActionError {
+    fn from(e: ComponentError) -> ActionError {
+        ActionError::Component(e)
+    }
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ActionError::NoPlayer => f.write_str("You're no longer here."),
+            ActionError::PlayerDead => f.write_str("You're dead."),
+            ActionError::Unplaced(_) => f.write_str("That isn't anywhere."),
+            ActionError::Blocked(_) => f.write_str("That way is blocked."),
+            ActionError::FullHealth => f.write_str("You're already at full health."),
+            ActionError::MissingItem(EntityType::Bow) => f.write_str("You don't have a bow."),
+            ActionError::MissingItem(EntityType::Corpse) => {
+                f.write_str("You aren't carrying a corpse.")
+            }
+            ActionError::MissingItem(EntityType::Herb) => {
+                f.write_str("You don't have any healing herbs.")
+            }
+            ActionError::MissingItem(t) => write!(f, "You don't have any {}s.", t.data().name),
+            ActionError::AlreadyRecalling => f.write_str("You've already read your scroll."),
+            ActionError::NoCorpseHere => f.write_str("There's no corpse here."),
+            ActionError::NoStairsDown => f.write_str("There's no way down here."),
+            ActionError::NoStairsUp => f.write_str("There's no way up here."),
+            ActionError::TargetIsSelf => f.write_str("You can't target yourself."),
+            ActionError::OutOfSight(_) => f.write_str("You can't see that far."),
+            ActionError::NotAnActor(_) => f.write_str("That can't fight."),
+            ActionError::FriendlyTarget(_) => f.write_str("You can't attack that."),
+            ActionError::Component(ComponentError::Stale) => {
+                f.write_str("That isn't there any more.")
+            }
+            ActionError::Component(ComponentError::Missing) => {
+                f.write_str("That isn't something you can do that with.")
+            }
+        }
+    }
+}
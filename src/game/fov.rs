@@ -1,9 +1,11 @@
 use ::engine::*;
 use super::{Game, Position, Tile};
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct IsVisible(pub i8);
 impl Component for IsVisible {}
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct WasVisible(pub Tile);
 impl Component for WasVisible {}
 
@@ -17,10 +19,10 @@ pub fn update_fov(game: &mut Game) {
         insert(game, pos, 0, view_distance);
         for quadrant in 0..4 {
             let pt = |x, y| match quadrant {
-                0 => add_offset(pos, x, y),
-                1 => add_offset(pos, -y, x),
-                2 => add_offset(pos, -x, -y),
-                3 => add_offset(pos, y, -x),
+                0 => pos + Offset { x, y },
+                1 => pos + Offset { x: -y, y: x },
+                2 => pos - Offset { x, y },
+                3 => pos + Offset { x: y, y: -x },
                 _ => unreachable!(),
             };
 
@@ -86,10 +88,6 @@ fn none_obstructed(game: &Game, positions: &[Position]) -> bool {
     positions.iter().all(|&pos| not_obstructed(game, pos))
 }
 
-fn add_offset(pos: Position, x: i32, y: i32) -> Position {
-    Position { x: pos.x + x, y: pos.y + y }
-}
-
 fn insert(game: &mut Game, pos: Position, distance: i8, view_distance: i8) {
     if view_distance >= distance {
         let tile = game.get_tile(pos);
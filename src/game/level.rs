@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use rand::{SeedableRng, StdRng};
+
+use super::*;
+
+/// How far below the surface a level is. Each level is another basin further
+/// down, reached by stairs cut into the cliffs.
+#[derive(Debug, Copy, Clone, Default, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct LevelId(pub u32);
+
+/// The only level without stairs down.
+pub const DEEPEST_LEVEL: LevelId = LevelId(2);
+
+// Levels below the surface are generated from this, so the same game always
+// has the same levels no matter when the player gets to them
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct GameSeed(pub u64);
+
+impl Resource for GameSeed {}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct CurrentLevel(pub LevelId);
+
+impl Resource for CurrentLevel {}
+
+// Set by taking the stairs. The travel system moves the player at the start
+// of the next turn.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Travel(pub Option<LevelId>);
+
+impl Resource for Travel {}
+
+// The map of a level the player has left. Entities left behind stay in the
+// world, with a Location::OtherLevel.
+#[derive(Clone, PartialEq)]
+struct StoredLevel {
+    tiles: BTreeMap<Position, Tile>,
+    seen: BTreeMap<Position, WasVisible>,
+    // Where the player left from, and comes back to
+    exit: Position,
+}
+
+#[derive(Clone, Default, PartialEq)]
+pub struct StoredLevels(BTreeMap<LevelId, StoredLevel>);
+
+impl fmt::Debug for StoredLevels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl Resource for StoredLevels {}
+
+/// Only the level the player is on is simulated. Every other level is put
+/// away as it was left, until the player comes back.
+pub fn travel(g: &mut Game) {
+    let to = match g.world.resource::<Travel>().0 {
+        Some(to) => to,
+        None => return,
+    };
+    g.world.resource_mut::<Travel>().0 = None;
+    let (player, exit) = match (g.player(), g.player_position()) {
+        (Ok(player), Ok(pos)) => (player, pos),
+        _ => return,
+    };
+
+    let from = g.world.resource::<CurrentLevel>().0;
+    let stored = stash_level(g, from, player, exit);
+    g.world.resource_mut::<StoredLevels>().0.insert(from, stored);
+
+    g.world.resource_mut::<CurrentLevel>().0 = to;
+    let arrival = match g.world.resource_mut::<StoredLevels>().0.remove(&to) {
+        Some(stored) => restore_level(g, to, stored),
+        None => generate_level(g, to),
+    };
+    let arrival = free_position_near(g, arrival);
+    g.world.set_location(player, Location::Position(arrival));
+}
+
+// Everything on the map except the player is moved off it
+fn stash_level(g: &mut Game, level: LevelId, player: Entity, exit: Position) -> StoredLevel {
+    let left_behind: Vec<(Entity, Position)> = g.world.component::<Location>().iter()
+        .filter_map(|(id, &location)| match location {
+            Location::Position(pos) if id != player => Some((id, pos)),
+            _ => None,
+        })
+        .collect();
+    for (id, pos) in left_behind {
+        g.world.set_location(id, Location::OtherLevel(level, pos));
+    }
+
+    let stored = StoredLevel {
+        tiles: g.world.component::<Tile>().iter().map(|(pos, &tile)| (pos, tile)).collect(),
+        seen: g.world.component::<WasVisible>().iter().map(|(pos, seen)| (pos, seen.clone())).collect(),
+        exit,
+    };
+    // Nothing needs to know about these tiles going away, so the storages
+    // are cleared directly
+    g.world.component_mut::<Tile>().clear();
+    g.world.component_mut::<WasVisible>().clear();
+    g.world.component_mut::<IsVisible>().clear();
+    g.world.component_mut::<Smell>().clear();
+    *g.world.resource_mut::<TileIndex>() = TileIndex::default();
+    stored
+}
+
+fn restore_level(g: &mut Game, level: LevelId, stored: StoredLevel) -> Position {
+    for (pos, tile) in stored.tiles {
+        g.world.entity_mut(pos).insert(tile);
+    }
+    for (pos, seen) in stored.seen {
+        g.world.entity_mut(pos).insert(seen);
+    }
+
+    let returning: Vec<(Entity, Position)> = g.world.component::<Location>().iter()
+        .filter_map(|(id, &location)| match location {
+            Location::OtherLevel(l, pos) if l == level => Some((id, pos)),
+            _ => None,
+        })
+        .collect();
+    for (id, pos) in returning {
+        g.world.set_location(id, Location::Position(pos));
+    }
+    stored.exit
+}
+
+// Uses its own generator, so that playing differently doesn't change the map
+fn generate_level(g: &mut Game, level: LevelId) -> Position {
+    let seed = g.world.resource::<GameSeed>().0;
+    let level_rng = StdRng::from_seed(&[seed as usize, level.0 as usize]);
+    let game_rng = ::std::mem::replace(g.rand(), level_rng);
+    let start = map::init_level(g, level);
+    *g.rand() = game_rng;
+    start
+}
+
+// Something may have wandered onto the stairs while the player was away
+fn free_position_near(g: &Game, pos: Position) -> Position {
+    ::std::iter::once(pos).chain(pos.ring(1))
+        .find(|&p| g.get_tile(p).is_walkable() && g.get_actor_by_position(p).is_err())
+        .unwrap_or(pos)
+}
@@ -2,6 +2,8 @@ use std::collections::{BinaryHeap, HashSet, HashMap};
 use rand::Rng;
 
 use super::*;
+use super::map_config::Loot;
+use super::vault::Vault;
 
 trait Feature {
     fn overwrite(&mut self, g: &mut Game, pos: Position, dist: i32) -> Option<i32>;
@@ -23,7 +25,7 @@ impl<F: TerrainFeature> Feature for F {
             return None;
         }
 
-        let (tile, dist_increase) = self.pick_tile(dist, &mut g.rand);
+        let (tile, dist_increase) = self.pick_tile(dist, g.rand());
         g.world.entity_mut(pos).insert(tile);
         Some(dist_increase)
     }
@@ -87,20 +89,199 @@ impl Feature for Trees {
             return None;
         }
 
-        if dist > self.1 && g.rand.gen_range(0, self.2) == 0 {
+        if dist > self.1 && g.rand().gen_range(0, self.2) == 0 {
             add_feature_at(g, Tree, Some(pos));
         } else {
             g.world.entity_mut(pos).insert(Tile::Ground);
         }
-        Some(g.rand.gen_range(0, self.1))
+        Some(g.rand().gen_range(0, self.1))
+    }
+}
+
+// Juts in from the cliffs, with scree around it
+struct Outcrop(i32);
+
+impl TerrainFeature for Outcrop {
+    fn pick_tile<R: Rng>(&mut self, distance: i32, rand: &mut R) -> (Tile, i32) {
+        if rand.gen_range(0, self.size()) + self.size()/3 < distance {
+            (Tile::Ground, rand.gen_range(2, 4))
+        } else {
+            (Tile::Rock, rand.gen_range(1, 4))
+        }
+    }
+
+    fn size(&self) -> i32 { self.0 }
+}
+
+// Carved out of the cliffs, so it only grows where there's nothing yet
+struct Cave(i32);
+
+impl TerrainFeature for Cave {
+    fn pick_tile<R: Rng>(&mut self, _: i32, rand: &mut R) -> (Tile, i32) {
+        (Tile::CaveFloor, rand.gen_range(1, 3))
+    }
+
+    fn size(&self) -> i32 { self.0 }
+
+    fn can_overwrite(&self, t: Tile) -> bool {
+        t == Tile::Wall
+    }
+}
+
+struct BoulderField(i32, i32);
+
+impl TerrainFeature for BoulderField {
+    fn pick_tile<R: Rng>(&mut self, _: i32, rand: &mut R) -> (Tile, i32) {
+        if rand.gen_range(0, self.1) == 0 {
+            (Tile::Boulder, rand.gen_range(1, 3))
+        } else {
+            (Tile::Ground, rand.gen_range(1, 3))
+        }
+    }
+
+    fn size(&self) -> i32 { self.0 }
+}
+
+struct Bog(i32);
+
+impl TerrainFeature for Bog {
+    fn pick_tile<R: Rng>(&mut self, distance: i32, rand: &mut R) -> (Tile, i32) {
+        if rand.gen_range(0, self.size()) + self.size()/2 < distance {
+            (Tile::LongGrass, rand.gen_range(2, 4))
+        } else if rand.gen_range(0, 5) == 0 {
+            (Tile::ShallowWater, rand.gen_range(1, 3))
+        } else {
+            (Tile::Bog, rand.gen_range(1, 3))
+        }
+    }
+
+    fn size(&self) -> i32 { self.0 }
+}
+
+// Stone walls around a rubble floor, with the loot in the middle and only a
+// couple of ways in. Built all at once where it first fits, so nothing else
+// is overwritten after that.
+struct Ruin {
+    width: i32,
+    height: i32,
+    loot: Vec<Loot>,
+    built: bool,
+}
+
+impl Feature for Ruin {
+    fn overwrite(&mut self, g: &mut Game, pos: Position, _: i32) -> Option<i32> {
+        if self.built {
+            return None;
+        }
+        let rect = Rect {
+            min_x: pos.x - self.width/2,
+            max_x: pos.x - self.width/2 + self.width - 1,
+            min_y: pos.y - self.height/2,
+            max_y: pos.y - self.height/2 + self.height - 1,
+        };
+        // With room to walk all the way around it
+        let mut surroundings = rect;
+        surroundings.extend(Position { x: rect.min_x - 1, y: rect.min_y - 1 });
+        surroundings.extend(Position { x: rect.max_x + 1, y: rect.max_y + 1 });
+        if !surroundings.positions().all(|p| g.get_tile(p) == Tile::BoringGround) {
+            return None;
+        }
+
+        let mut walls = vec![];
+        for p in rect.positions() {
+            let on_x_edge = p.x == rect.min_x || p.x == rect.max_x;
+            let on_y_edge = p.y == rect.min_y || p.y == rect.max_y;
+            if !on_x_edge && !on_y_edge {
+                g.world.entity_mut(p).insert(Tile::Rubble);
+                continue;
+            }
+            // Some of the walls have fallen down, but not enough to get past
+            let tile = if g.rand().gen_range(0, 8) == 0 { Tile::Boulder } else { Tile::StoneWall };
+            g.world.entity_mut(p).insert(tile);
+            if on_x_edge != on_y_edge {
+                walls.push(p);
+            }
+        }
+        for _ in 0..2 {
+            if let Some(&doorway) = g.rand().choose(&walls) {
+                g.world.entity_mut(doorway).insert(Tile::Rubble);
+            }
+        }
+
+        place_loot(g, pos, &self.loot);
+        self.built = true;
+        Some(0)
+    }
+}
+
+// Stamped all at once where it first fits on open ground
+struct VaultSite<'a> {
+    vault: &'a Vault,
+    turns: u32,
+    mirrored: bool,
+    // Where vaults have been built so far
+    sites: &'a mut Vec<Position>,
+    built: bool,
+}
+
+impl<'a> Feature for VaultSite<'a> {
+    fn overwrite(&mut self, g: &mut Game, pos: Position, _: i32) -> Option<i32> {
+        if self.built {
+            return None;
+        }
+        let cells: Vec<_> = self.vault.cells(self.turns, self.mirrored)
+            .map(|(offset, stamp)| (pos + offset, stamp))
+            .collect();
+        // With room to walk all the way around it
+        let fits = cells.iter().all(|&(p, _)| {
+            ::std::iter::once(p).chain(p.ring(1)).all(|p| g.get_tile(p) == Tile::BoringGround)
+        });
+        if !fits {
+            return None;
+        }
+
+        for (p, stamp) in cells {
+            g.world.entity_mut(p).insert(stamp.tile);
+            place_loot(g, p, &stamp.loot);
+        }
+        self.sites.push(pos);
+        self.built = true;
+        Some(0)
+    }
+}
+
+fn choose_vault<'a, R: Rng>(vaults: &'a [Vault], level: LevelId, rand: &mut R) -> Option<&'a Vault> {
+    let allowed: Vec<&Vault> = vaults.iter().filter(|v| v.min_level <= level.0 && v.weight > 0).collect();
+    let total: u32 = allowed.iter().map(|v| v.weight).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut roll = rand.gen_range(0, total);
+    allowed.into_iter().find(|v| {
+        if roll < v.weight {
+            true
+        } else {
+            roll -= v.weight;
+            false
+        }
+    })
+}
+
+fn place_loot(g: &mut Game, pos: Position, loot: &[Loot]) {
+    for loot in loot {
+        let count = if loot.max > loot.min {
+            g.rand().gen_range(loot.min, loot.max + 1)
+        } else {
+            loot.min
+        };
+        for _ in 0..count {
+            g.put_entity(loot.entity, pos);
+        }
     }
 }
 
-// TODO: This is painfully inefficient. Can I do better?
 fn rand_position(g: &mut Game) -> Position {
-    let count = g.world.component::<Tile>().count();
-    let index = g.rand.gen_range(0, count);
-    g.world.component::<Tile>().ids().nth(index).unwrap_or(Position { x: 0, y: 0 })
+    g.random_tile().unwrap_or(Position { x: 0, y: 0 })
 }
 
 fn select_position<P: FnMut(Tile) -> bool>(g: &mut Game, mut predicate: P) -> Option<Position> {
@@ -137,7 +318,7 @@ fn add_feature_at<F: Feature>(g: &mut Game, mut f: F, pos: Option<Position>) {
         for &dir in &ALL_DIRECTIONS {
             let next_pos = pos.step(dir);
             if !visited.contains(&next_pos)
-                && (dir.is_orthogonal() || g.rand.gen_range(0, 5) < 2)
+                && (dir.is_orthogonal() || g.rand().gen_range(0, 5) < 2)
             {
                 if let Some(dist_increase) = f.overwrite(g, next_pos, -priority) {
                     queue.push((priority-dist_increase, next_pos));
@@ -155,11 +336,12 @@ fn add_feature<F: Feature>(g: &mut Game, f: F) {
 fn find_map_edge(g: &mut Game) -> Option<Position> {
     let mut count = 0;
     let mut map_edge = None;
-    for pos in g.world.component::<Tile>().ids() {
+    let tiles: Vec<_> = g.world.component::<Tile>().ids().collect();
+    for pos in tiles {
         for &dir in &ORTHOGONAL_DIRECTIONS {
             if g.get_tile(pos.step(dir)) == Tile::Wall {
                 count += 1;
-                if g.rand.gen_range(0, count) == 0 {
+                if g.rand().gen_range(0, count) == 0 {
                     map_edge = Some(pos);
                 }
             }
@@ -168,7 +350,17 @@ fn find_map_edge(g: &mut Game) -> Option<Position> {
     map_edge
 }
 
-fn create_ground(g: &mut Game) {
+// A spot in the cliffs right next to the basin
+fn find_cliff_face(g: &mut Game) -> Option<Position> {
+    let edge = find_map_edge(g)?;
+    let cliffs: Vec<Position> = ORTHOGONAL_DIRECTIONS.iter()
+        .map(|&dir| edge.step(dir))
+        .filter(|&pos| g.get_tile(pos) == Tile::Wall)
+        .collect();
+    g.rand().choose(&cliffs).cloned()
+}
+
+fn create_ground(g: &mut Game, size: usize) {
     let mut queue = BinaryHeap::new();
     let mut visited = HashSet::new();
     let initial_pos = Position { x: 0, y: 0 };
@@ -176,15 +368,15 @@ fn create_ground(g: &mut Game) {
     queue.push((0, initial_pos));
     visited.insert(initial_pos);
 
-    while visited.len() < 4000 {
+    while visited.len() < size {
         let (priority, pos) = queue.pop().unwrap();
         for &dir in &ALL_DIRECTIONS {
             let next_pos = pos.step(dir);
             if !visited.contains(&next_pos) && (
-                dir.is_orthogonal() || g.rand.gen_range(0, 5) < 2
+                dir.is_orthogonal() || g.rand().gen_range(0, 5) < 2
             ) {
                 g.world.entity_mut(next_pos).insert(Tile::BoringGround);
-                let next_priority = priority - g.rand.gen_range(0, 3);
+                let next_priority = priority - g.rand().gen_range(0, 3);
                 queue.push((next_priority, next_pos));
                 visited.insert(next_pos);
             }
@@ -239,7 +431,7 @@ impl Feature for River {
             Some(- 1)
         } else if tile == Tile::BoringGround {
             self.order.insert(pos, count);
-            Some(g.rand.gen_range(1, 3))
+            Some(g.rand().gen_range(1, 3))
         } else {
             None
         }
@@ -250,7 +442,7 @@ fn add_river(g: &mut Game, start: Position, end: Position) {
     add_feature_at(
         g,
         River {
-            end: end,
+            end,
             order: HashMap::new(),
             max_distance: 0,
             done: false,
@@ -259,9 +451,60 @@ fn add_river(g: &mut Game, start: Position, end: Position) {
     );
 }
 
-fn place_player(g: &mut Game) {
+// Longer than this and nobody would bother
+const MAX_CROSSING: i32 = 8;
+
+// Every straight line of deep water with something walkable at both ends,
+// going east or south so that each is only found once
+fn find_crossings(g: &Game) -> Vec<Vec<Position>> {
+    let mut crossings = vec![];
+    for (pos, &tile) in g.world.component::<Tile>().iter() {
+        if !tile.is_walkable() {
+            continue;
+        }
+        for &dir in &[Direction::East, Direction::South] {
+            let mut crossing = vec![];
+            let mut next = pos.step(dir);
+            while g.get_tile(next) == Tile::DeepWater && crossing.len() < MAX_CROSSING as usize {
+                crossing.push(next);
+                next = next.step(dir);
+            }
+            if !crossing.is_empty() && g.get_tile(next).is_walkable() {
+                crossings.push(crossing);
+            }
+        }
+    }
+    crossings
+}
+
+// Short crossings are forded, and longer ones are bridged. They're kept apart
+// so there aren't several side by side.
+fn add_crossings(g: &mut Game, count: u32) {
+    let mut crossings = find_crossings(g);
+    let mut built: Vec<Position> = vec![];
+    while (built.len() as u32) < count && !crossings.is_empty() {
+        let index = g.rand().gen_range(0, crossings.len());
+        let crossing = crossings.swap_remove(index);
+        let middle = crossing[crossing.len()/2];
+        if built.iter().any(|&other| other.distance_sq(middle) < MAX_CROSSING*MAX_CROSSING) {
+            continue;
+        }
+        let tile = if crossing.len() <= 2 { Tile::ShallowWater } else { Tile::Bridge };
+        for pos in crossing {
+            g.world.entity_mut(pos).insert(tile);
+        }
+        built.push(middle);
+    }
+}
+
+// Somewhere well away from a random point, with no wall next to it and no
+// vault nearby
+fn find_start(g: &mut Game, vault_sites: &[Position]) -> Position {
     let pt = rand_position(g);
     let player_pos = g.world.component::<Tile>().ids().filter(|&pos| {
+        if vault_sites.iter().any(|&site| site.distance_sq(pos) < 16*16) {
+            return false;
+        }
         if ![Tile::Ground, Tile::BoringGround, Tile::ShortGrass, Tile::LongGrass]
             .contains(&g.get_tile(pos))
         {
@@ -273,137 +516,169 @@ fn place_player(g: &mut Game) {
             }
         }
         true
-    }).max_by_key(|pos| {
-        (pt.x - pos.x)*(pt.x - pos.x) + (pt.y - pos.y)*(pt.y - pos.y)
-    }).unwrap();
-    g.put_entity(EntityType::Player, player_pos);
+    }).max_by_key(|&pos| pt.distance_sq(pos));
+    // Configs with hardly any land might not leave anywhere better, and the
+    // connectivity pass will make the best of it
+    player_pos.unwrap_or(pt)
 }
 
-pub fn init_game(g: &mut Game) {
-    create_ground(g);
+/// Generates the map and everything on it for a level, and returns where
+/// the player starts out. That's where the player is put on the surface, and
+/// where the stairs up are on the levels below it.
+pub fn init_level(g: &mut Game, level: LevelId) -> Position {
+    let config = g.world.resource::<MapGenConfig>().clone();
+    create_ground(g, config.ground_tiles);
+
+    if let Some((&(size, depth), others)) = config.lakes.split_first() {
+        let lake_pos = rand_position(g);
+        add_feature_at(g, Lake(size, depth), Some(lake_pos));
+        for &(size, depth) in others {
+            add_feature(g, Lake(size, depth));
+        }
 
-    let lake_pos = rand_position(g);
-    add_feature_at(g, Lake(16, 3), Some(lake_pos));
+        if let Some(map_edge) = find_map_edge(g) {
+            add_river(g, map_edge, lake_pos);
+        }
+    }
+    add_crossings(g, config.crossings);
 
-    add_feature(g, Lake(12, 1));
-    add_feature(g, Lake(8, 1));
-    add_feature(g, Lake(8, 2));
-    add_feature(g, Lake(8, 3));
+    for &size in &config.outcrops {
+        if let Some(edge) = find_map_edge(g) {
+            add_feature_at(g, Outcrop(size), Some(edge));
+        }
+    }
+    for &size in &config.caves {
+        if let Some(cliff) = find_cliff_face(g) {
+            add_feature_at(g, Cave(size), Some(cliff));
+        }
+    }
 
-    if let Some(map_edge) = find_map_edge(g) {
-        add_river(g, map_edge, lake_pos);
+    for &(width, height) in &config.ruins {
+        add_feature(g, Ruin { width, height, loot: config.ruin_loot.clone(), built: false });
+    }
+    let mut vault_sites = vec![];
+    for _ in 0..config.vault_count {
+        let vault = match choose_vault(&config.vaults, level, g.rand()) {
+            Some(vault) => vault,
+            None => break,
+        };
+        let turns = g.rand().gen_range(0, 4);
+        let mirrored = g.rand().gen();
+        add_feature(g, VaultSite { vault, turns, mirrored, sites: &mut vault_sites, built: false });
     }
 
-    add_feature(g, Trees(64, 7, 3));
-    add_feature(g, Trees(32, 5, 5));
-    add_feature(g, Trees(16, 3, 7));
-    for _ in 0..16 {
-        add_feature(g, Tree);
+    for &(size, sparseness) in &config.boulder_fields {
+        add_feature(g, BoulderField(size, sparseness));
+    }
+    for &size in &config.bogs {
+        add_feature(g, Bog(size));
     }
 
-    add_feature(g, Meadow(16, 1));
-    add_feature(g, Meadow(16, 1));
-    add_feature(g, Meadow(12, 1));
-    add_feature(g, Meadow(8, 1));
-    add_feature(g, Meadow(6, 1));
-    add_feature(g, Meadow(4, 1));
+    for &(size, spacing, sparseness) in &config.forests {
+        add_feature(g, Trees(size, spacing, sparseness));
+    }
+    for _ in 0..config.lone_trees {
+        add_feature(g, Tree);
+    }
 
-    add_feature(g, Meadow(24, 7));
-    add_feature(g, Meadow(12, 5));
-    add_feature(g, Meadow(6, 3));
+    for &(size, sparseness) in &config.meadows {
+        add_feature(g, Meadow(size, sparseness));
+    }
 
-    place_player(g);
+    let start = find_start(g, &vault_sites);
+    if level == LevelId(0) {
+        g.put_entity(EntityType::Player, start);
+    } else {
+        g.world.entity_mut(start).insert(Tile::StairsUp);
+    }
 
     let is_land = |tile| [
         Tile::Ground, Tile::BoringGround, Tile::ShortGrass, Tile::LongGrass,
     ].contains(&tile);
 
-    {
-        let mut randomly_place = |entity_type, count| {
-            for _ in 0..count {
-                if let Some(pos) = select_position(g, &is_land) {
-                    g.put_entity(entity_type, pos);
-                }
-            }
-        };
-
-        randomly_place(EntityType::Rock, 32);
-        randomly_place(EntityType::Herb, 8);
-        randomly_place(EntityType::Rat, 8);
-        randomly_place(EntityType::Deer, 8);
-        randomly_place(EntityType::Wolf, 4);
-    }
-
-    let mut diamond_count = 0;
-
-    for _ in 0..2 { // 0 + 2 * 3 = 6
-        if let Some(pos) = select_position(g, &is_land) {
-            g.put_entity(EntityType::Skeleton, pos);
-            for _ in 0..g.rand.gen_range(0, 1) {
-                g.put_entity(EntityType::Herb, pos);
-            }
-            for _ in 0..g.rand.gen_range(1, 3) {
-                g.put_entity(EntityType::Diamond, pos);
-                diamond_count += 1;
-            }
-            for _ in 0..g.rand.gen_range(3, 5) {
-                g.put_entity(EntityType::Arrow, pos);
-            }
-            g.put_entity(EntityType::Bow, pos);
+    // Cut into the cliffs, or out in the open if there's no cliff to be found
+    if level < DEEPEST_LEVEL {
+        if let Some(pos) = find_cliff_face(g).or_else(|| select_position(g, &is_land)) {
+            g.world.entity_mut(pos).insert(Tile::StairsDown);
         }
     }
 
-    for _ in 0..2 { // 6 + 2 * 3 = 12
-        if let Some(pos) = select_position(g, &is_land) {
-            g.put_entity(EntityType::Skeleton, pos);
-            for _ in 0..g.rand.gen_range(0, 2) {
-                g.put_entity(EntityType::Herb, pos);
+    // Further down is more dangerous
+    for spawn in &config.spawns {
+        for _ in 0..spawn.count + spawn.per_level*level.0 {
+            if let Some(pos) = select_position(g, &is_land) {
+                g.put_entity(spawn.entity, pos);
             }
-            for _ in 0..g.rand.gen_range(1, 3) {
-                g.put_entity(EntityType::Diamond, pos);
-                diamond_count += 1;
-            }
-            g.put_entity(EntityType::Sword, pos);
         }
     }
 
-    for _ in 0..2 { // 12 + 2 * 5 = 22
-        if let Some(pos) = select_position(g, &is_land) {
-            g.put_entity(EntityType::Skeleton, pos);
-            for _ in 0..g.rand.gen_range(0, 1) {
-                g.put_entity(EntityType::Herb, pos);
-            }
-            for _ in 0..g.rand.gen_range(2, 5) {
-                g.put_entity(EntityType::Diamond, pos);
-                diamond_count += 1;
-            }
-            for _ in 0..g.rand.gen_range(0, 3) {
-                g.put_entity(EntityType::Arrow, pos);
-            }
-            for _ in 0..g.rand.gen_range(0, 1) {
-                g.put_entity(EntityType::Sword, pos);
+    for cache in &config.caches {
+        for _ in 0..cache.count + cache.per_level*level.0 {
+            if let Some(pos) = select_position(g, &is_land) {
+                place_loot(g, pos, &cache.loot);
             }
-            for _ in 0..g.rand.gen_range(0, 1) {
-                g.put_entity(EntityType::Bow, pos);
-            }
-            g.put_entity(EntityType::Dragon, pos);
         }
     }
 
-    for _ in 0..3 { // 22 + 3 * 2 = 28
-        if let Some(pos) = select_position(g, &is_land) {
-            g.put_entity(EntityType::Skeleton, pos);
-            for _ in 0..g.rand.gen_range(0, 2) {
-                g.put_entity(EntityType::Diamond, pos);
-                diamond_count += 1;
-            }
+    // Including any in ruins, but not the ones left on other levels
+    let mut diamond_count = g.world.query::<(&EntityType, &Location)>()
+        .filter(|&(_, (&t, location))| {
+            t == EntityType::Diamond && matches!(*location, Location::Position(_))
+        })
+        .count() as u32;
+
+    // Gives up eventually if there's hardly any land to put them on
+    for _ in 0..config.min_diamonds*100 {
+        if diamond_count >= config.min_diamonds {
+            break;
         }
-    }
-
-    while diamond_count < 30 {
         if let Some(pos) = select_position(g, &is_land) {
             g.put_entity(EntityType::Diamond, pos);
             diamond_count += 1;
         }
     }
+
+    let report = connect_level(g, start);
+    *g.world.resource_mut::<ConnectivityReport>() = report;
+
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, StdRng};
+
+    use super::*;
+    use game::vault::parse_vaults;
+
+    #[test]
+    fn chooses_vaults_by_weight() {
+        let vaults = parse_vaults("
+[rare]
+.
+[common]
+weight = 3
+.
+[never]
+weight = 0
+.
+[deep]
+min_level = 2
+weight = 100
+.
+").unwrap();
+        let mut rand = StdRng::from_seed(&[1]);
+        let mut counts = HashMap::new();
+        for _ in 0..4000 {
+            let vault = choose_vault(&vaults, LevelId(1), &mut rand).unwrap();
+            *counts.entry(vault.name.as_str()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 2);
+        assert!((800..1200).contains(&counts["rare"]), "{:?}", counts);
+        assert_eq!(counts["rare"] + counts["common"], 4000);
+
+        // Nothing left once the others are too shallow or never picked
+        assert_eq!(choose_vault(&vaults[2..], LevelId(1), &mut rand), None);
+        assert_eq!(choose_vault(&vaults[2..], LevelId(2), &mut rand), Some(&vaults[3]));
+    }
 }
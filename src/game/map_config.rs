@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ::engine::*;
+use super::EntityType;
+use super::vault::{builtin_vaults, load_vaults, Vault};
+
+/// The presets `MapGenConfig::preset` knows about.
+pub static PRESETS: [&str; 4] = ["small", "default", "huge", "dense_forest"];
+
+/// Everything about a level's map that's up to chance, apart from the
+/// chance itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapGenConfig {
+    /// The preset this started from, or the file it was loaded from.
+    pub name: String,
+    /// How many tiles the basin has before anything is added to it.
+    pub ground_tiles: usize,
+    /// Size, and how much of that is deep water in the middle. The river runs
+    /// into the first lake.
+    pub lakes: Vec<(i32, i32)>,
+    /// How many bridges and fords there are over deep water.
+    pub crossings: u32,
+    /// Size, how far apart trees are, and how sparse they are.
+    pub forests: Vec<(i32, i32, i32)>,
+    pub lone_trees: u32,
+    /// Size, and how sparse the long grass is.
+    pub meadows: Vec<(i32, i32)>,
+    /// Sizes of the rocky outcrops jutting in from the cliffs.
+    pub outcrops: Vec<i32>,
+    /// Sizes of the caves cut back into the cliffs.
+    pub caves: Vec<i32>,
+    /// Width and height of ruined stone buildings, each with `ruin_loot`
+    /// inside.
+    pub ruins: Vec<(i32, i32)>,
+    pub ruin_loot: Vec<Loot>,
+    /// Size, and how sparse the boulders are.
+    pub boulder_fields: Vec<(i32, i32)>,
+    /// Sizes of the bogs, which take twice as long to wade through.
+    pub bogs: Vec<i32>,
+    pub spawns: Vec<Spawn>,
+    pub caches: Vec<Cache>,
+    /// Hand drawn places to choose from, by weight.
+    pub vaults: Vec<Vault>,
+    /// How many vaults each level tries to fit in.
+    pub vault_count: u32,
+    /// Diamonds are scattered around until there are at least this many.
+    pub min_diamonds: u32,
+}
+
+/// Entities placed one at a time on random land.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spawn {
+    pub entity: EntityType,
+    pub count: u32,
+    /// Extra ones for each level below the surface.
+    pub per_level: u32,
+}
+
+/// Piles of loot, each on a random patch of land.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cache {
+    pub count: u32,
+    /// Extra piles for each level below the surface.
+    pub per_level: u32,
+    /// Put on the pile in order, so any creature guarding it should go last.
+    pub loot: Vec<Loot>,
+}
+
+/// Between `min` and `max` of something, inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loot {
+    pub entity: EntityType,
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownPreset(String),
+    Io(String, io::Error),
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::UnknownPreset(ref name) => write!(
+                f, "There's no preset called {}. Try one of: {}", name, PRESETS.join(", "),
+            ),
+            ConfigError::Io(ref path, ref e) => write!(f, "Couldn't read {}: {}", path, e),
+            ConfigError::Parse { line, ref reason } => write!(f, "Line {}: {}", line, reason),
+        }
+    }
+}
+
+fn spawn(entity: EntityType, count: u32, per_level: u32) -> Spawn {
+    Spawn { entity, count, per_level }
+}
+
+fn loot(entity: EntityType, min: u32, max: u32) -> Loot {
+    Loot { entity, min, max }
+}
+
+impl Default for MapGenConfig {
+    fn default() -> MapGenConfig {
+        use self::EntityType::*;
+        MapGenConfig {
+            name: String::from("default"),
+            ground_tiles: 4000,
+            lakes: vec![(16, 3), (12, 1), (8, 1), (8, 2), (8, 3)],
+            crossings: 4,
+            forests: vec![(64, 7, 3), (32, 5, 5), (16, 3, 7)],
+            lone_trees: 16,
+            meadows: vec![
+                (16, 1), (16, 1), (12, 1), (8, 1), (6, 1), (4, 1),
+                (24, 7), (12, 5), (6, 3),
+            ],
+            outcrops: vec![8, 6, 4],
+            caves: vec![6, 4],
+            ruins: vec![(9, 7), (7, 5)],
+            ruin_loot: vec![loot(Skeleton, 1, 1), loot(Diamond, 1, 3), loot(Herb, 0, 1)],
+            boulder_fields: vec![(12, 4), (8, 3)],
+            bogs: vec![12, 8],
+            spawns: vec![
+                spawn(Rock, 32, 0),
+                spawn(Herb, 8, 0),
+                spawn(Raft, 1, 0),
+                spawn(Rat, 8, 0),
+                spawn(Deer, 8, 0),
+                spawn(Wolf, 4, 2),
+            ],
+            caches: vec![
+                Cache {
+                    count: 2,
+                    per_level: 0,
+                    loot: vec![loot(Skeleton, 1, 1), loot(Diamond, 1, 2), loot(Arrow, 3, 4), loot(Bow, 1, 1)],
+                },
+                Cache {
+                    count: 2,
+                    per_level: 0,
+                    loot: vec![loot(Skeleton, 1, 1), loot(Herb, 0, 1), loot(Diamond, 1, 2), loot(Sword, 1, 1)],
+                },
+                Cache {
+                    count: 2,
+                    per_level: 1,
+                    loot: vec![loot(Skeleton, 1, 1), loot(Diamond, 2, 4), loot(Arrow, 0, 2), loot(Dragon, 1, 1)],
+                },
+                Cache {
+                    count: 3,
+                    per_level: 0,
+                    loot: vec![loot(Skeleton, 1, 1), loot(Diamond, 0, 1)],
+                },
+            ],
+            vaults: builtin_vaults(),
+            vault_count: 2,
+            min_diamonds: 30,
+        }
+    }
+}
+
+impl Resource for MapGenConfig {}
+
+impl MapGenConfig {
+    pub fn preset(name: &str) -> Result<MapGenConfig, ConfigError> {
+        let mut config = MapGenConfig::default();
+        match name {
+            "default" => {}
+            "small" => {
+                config.ground_tiles = 2000;
+                config.lakes = vec![(12, 3), (8, 1), (8, 2)];
+                config.crossings = 2;
+                config.forests = vec![(32, 5, 5), (16, 3, 7)];
+                config.lone_trees = 8;
+                config.meadows.truncate(6);
+                config.outcrops.truncate(2);
+                config.caves.truncate(1);
+                config.ruins.truncate(1);
+                config.boulder_fields.truncate(1);
+                config.bogs.truncate(1);
+                config.vault_count = 1;
+                for spawn in &mut config.spawns {
+                    spawn.count /= 2;
+                }
+                config.min_diamonds = 15;
+            }
+            "huge" => {
+                config.ground_tiles = 10000;
+                config.lakes.extend_from_slice(&[(16, 2), (12, 3), (8, 1)]);
+                config.crossings = 8;
+                config.forests.extend_from_slice(&[(64, 7, 3), (32, 5, 5)]);
+                config.lone_trees = 40;
+                let meadows = config.meadows.clone();
+                config.meadows.extend(meadows);
+                config.outcrops.extend_from_slice(&[8, 6, 4]);
+                config.caves.extend_from_slice(&[8, 6, 4]);
+                config.ruins.extend_from_slice(&[(11, 9), (7, 7)]);
+                config.boulder_fields.extend_from_slice(&[(12, 4), (8, 3)]);
+                config.bogs.extend_from_slice(&[16, 8]);
+                config.vault_count = 5;
+                for spawn in &mut config.spawns {
+                    spawn.count = spawn.count*5/2;
+                }
+                for cache in &mut config.caches {
+                    cache.count *= 2;
+                }
+                config.min_diamonds = 60;
+            }
+            "dense_forest" => {
+                config.forests = vec![
+                    (96, 7, 2), (64, 5, 3), (64, 5, 3), (32, 3, 5), (32, 3, 5), (16, 3, 7),
+                ];
+                config.lone_trees = 48;
+                config.meadows.truncate(3);
+            }
+            _ => return Err(ConfigError::UnknownPreset(String::from(name))),
+        }
+        config.name = String::from(name);
+        Ok(config)
+    }
+
+    /// A preset if there's one called `arg`, and otherwise a file to load.
+    pub fn from_arg(arg: &str) -> Result<MapGenConfig, ConfigError> {
+        if PRESETS.contains(&arg) {
+            MapGenConfig::preset(arg)
+        } else {
+            MapGenConfig::load(arg)
+        }
+    }
+
+    pub fn load(path: &str) -> Result<MapGenConfig, ConfigError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(String::from(path), e))?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let mut config = MapGenConfig::parse_in(&text, dir)?;
+        config.name = String::from(path);
+        Ok(config)
+    }
+
+    /// Reads `key = value` lines, starting from the default preset. Anything
+    /// after a '#' is ignored. `preset` starts over from another preset.
+    /// `lake`, `forest`, `meadow`, `outcrop`, `cave`, `ruin`, `boulders`,
+    /// `bog`, `spawn`, `cache` and `vault_file` can be given more than once, and
+    /// replace the preset's list the first time they are, for example:
+    ///
+    /// ```text
+    /// preset = small
+    /// lake = 16 3
+    /// ruin = 9 7
+    /// ruin_loot = skeleton diamond:1-3
+    /// spawn = wolf 4 +2
+    /// cache = 2 +1 skeleton diamond:2-4 arrow:0-2 dragon
+    /// vault_file = vaults/lairs.txt
+    /// ```
+    ///
+    /// `vault_file` paths are relative to the current directory. `load`
+    /// makes them relative to the config file instead.
+    pub fn parse(text: &str) -> Result<MapGenConfig, ConfigError> {
+        MapGenConfig::parse_in(text, Path::new(""))
+    }
+
+    // `dir` is where vault files are looked for
+    fn parse_in(text: &str, dir: &Path) -> Result<MapGenConfig, ConfigError> {
+        let mut config = MapGenConfig::default();
+        let mut replaced = HashSet::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            config.parse_line(line, dir, &mut replaced).map_err(|reason| {
+                ConfigError::Parse { line: index + 1, reason }
+            })?;
+        }
+        Ok(config)
+    }
+
+    fn parse_line(&mut self, line: &str, dir: &Path, replaced: &mut HashSet<String>) -> Result<(), String> {
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().ok_or_else(|| String::from("expected key = value"))?.trim();
+        let words: Vec<&str> = value.split_whitespace().collect();
+
+        let lists = [
+            "lake", "forest", "meadow", "outcrop", "cave", "ruin", "boulders", "bog", "spawn", "cache",
+            "vault_file",
+        ];
+        if lists.contains(&key) && replaced.insert(String::from(key)) {
+            match key {
+                "lake" => self.lakes.clear(),
+                "forest" => self.forests.clear(),
+                "meadow" => self.meadows.clear(),
+                "outcrop" => self.outcrops.clear(),
+                "cave" => self.caves.clear(),
+                "ruin" => self.ruins.clear(),
+                "boulders" => self.boulder_fields.clear(),
+                "bog" => self.bogs.clear(),
+                "vault_file" => self.vaults.clear(),
+                "spawn" => self.spawns.clear(),
+                _ => self.caches.clear(),
+            }
+        }
+
+        match key {
+            "preset" => {
+                *self = MapGenConfig::preset(value).map_err(|e| e.to_string())?;
+                replaced.clear();
+            }
+            "ground_tiles" => { self.ground_tiles = number(value)?; }
+            "lone_trees" => { self.lone_trees = number(value)?; }
+            "crossings" => { self.crossings = number(value)?; }
+            "min_diamonds" => { self.min_diamonds = number(value)?; }
+            "vaults" => { self.vault_count = number(value)?; }
+            "vault_file" => {
+                let path = dir.join(value);
+                let vaults = load_vaults(&path.to_string_lossy()).map_err(|e| match e {
+                    ConfigError::Parse { line, reason } => format!("{} line {}: {}", value, line, reason),
+                    e => e.to_string(),
+                })?;
+                self.vaults.extend(vaults);
+            }
+            "lake" => {
+                let n = numbers(&words, 2)?;
+                self.lakes.push((n[0], n[1]));
+            }
+            "forest" => {
+                let n = numbers(&words, 3)?;
+                self.forests.push((n[0], n[1], n[2]));
+            }
+            "meadow" => {
+                let n = numbers(&words, 2)?;
+                self.meadows.push((n[0], n[1]));
+            }
+            "outcrop" => { self.outcrops.push(size(value)?); }
+            "cave" => { self.caves.push(size(value)?); }
+            "ruin" => {
+                let n = numbers(&words, 2)?;
+                self.ruins.push((n[0], n[1]));
+            }
+            "ruin_loot" => {
+                self.ruin_loot = words.iter().map(|&w| parse_loot(w)).collect::<Result<_, _>>()?;
+            }
+            "boulders" => {
+                let n = numbers(&words, 2)?;
+                self.boulder_fields.push((n[0], n[1]));
+            }
+            "bog" => { self.bogs.push(size(value)?); }
+            "spawn" => {
+                let entity = entity(words.first().cloned().unwrap_or(""))?;
+                let (count, per_level) = count(&words[1..])?;
+                self.spawns.push(Spawn { entity, count, per_level });
+            }
+            "cache" => {
+                let (count, per_level) = count(&words)?;
+                let skip = if words.get(1).is_some_and(|w| w.starts_with('+')) { 2 } else { 1 };
+                let loot = words.iter().skip(skip).map(|&w| parse_loot(w)).collect::<Result<_, _>>()?;
+                self.caches.push(Cache { count, per_level, loot });
+            }
+            _ => return Err(format!("unknown key {}", key)),
+        }
+        Ok(())
+    }
+}
+
+pub fn number<T: ::std::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse().map_err(|_| format!("expected a number, not {}", word))
+}
+
+// Sizes, and the spacings and sparsenesses that features roll against, all
+// have to be at least 1
+fn size(word: &str) -> Result<i32, String> {
+    let n = number(word)?;
+    if n < 1 {
+        return Err(format!("expected a number of at least 1, not {}", word));
+    }
+    Ok(n)
+}
+
+fn numbers(words: &[&str], n: usize) -> Result<Vec<i32>, String> {
+    if words.len() != n {
+        return Err(format!("expected {} numbers", n));
+    }
+    words.iter().map(|&w| size(w)).collect()
+}
+
+fn entity(word: &str) -> Result<EntityType, String> {
+    EntityType::from_name(word).ok_or_else(|| format!("unknown entity {}", word))
+}
+
+// A count, optionally followed by "+n" extra per level
+fn count(words: &[&str]) -> Result<(u32, u32), String> {
+    let count = number(words.first().cloned().unwrap_or(""))?;
+    let per_level = match words.get(1) {
+        Some(w) if w.starts_with('+') => number(&w[1..])?,
+        _ => 0,
+    };
+    Ok((count, per_level))
+}
+
+// "diamond", "diamond:2" or "diamond:2-4"
+pub fn parse_loot(word: &str) -> Result<Loot, String> {
+    let mut parts = word.splitn(2, ':');
+    let entity = entity(parts.next().unwrap_or(""))?;
+    let (min, max) = match parts.next() {
+        None => (1, 1),
+        Some(range) => {
+            let mut bounds = range.splitn(2, '-');
+            let min = number(bounds.next().unwrap_or(""))?;
+            let max = bounds.next().map(number).unwrap_or(Ok(min))?;
+            (min, max)
+        }
+    };
+    if min > max {
+        return Err(format!("{} has a smaller maximum than minimum", word));
+    }
+    Ok(Loot { entity, min, max })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn assert_rejected(line: &str) {
+        match MapGenConfig::parse(line) {
+            Err(ConfigError::Parse { line: 1, .. }) => {}
+            result => panic!("{:?} gave {:?}", line, result),
+        }
+    }
+
+    #[test]
+    fn finds_vault_files_next_to_the_config() {
+        let dir = env::temp_dir().join(format!("scavenger-config-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lairs.txt"), "[den]\n.\n").unwrap();
+        fs::write(dir.join("config.txt"), "vault_file = lairs.txt\n").unwrap();
+        let config = MapGenConfig::load(&dir.join("config.txt").to_string_lossy());
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<_> = config.unwrap().vaults.iter().map(|v| v.name.clone()).collect();
+        assert_eq!(names, vec!["den"]);
+    }
+
+    #[test]
+    fn parses_features() {
+        let config = MapGenConfig::parse("lake = 16 1\nforest = 64 1 1 # dense\nmeadow = 8 2").unwrap();
+        assert_eq!(config.lakes, vec![(16, 1)]);
+        assert_eq!(config.forests, vec![(64, 1, 1)]);
+        assert_eq!(config.meadows, vec![(8, 2)]);
+    }
+
+    #[test]
+    fn rejects_sizes_below_one() {
+        assert_rejected("lake = 16 0");
+        assert_rejected("lake = -4 2");
+        assert_rejected("forest = 64 0 0");
+        assert_rejected("forest = 64 7 0");
+        assert_rejected("meadow = 16 0");
+    }
+
+    #[test]
+    fn rejects_empty_and_inside_out_features() {
+        assert_rejected("ruin = -9 7");
+        assert_rejected("ruin = 9 0");
+        assert_rejected("boulders = 12 0");
+        assert_rejected("outcrop = 0");
+        assert_rejected("cave = -4");
+        assert_rejected("bog = 0");
+    }
+
+    #[test]
+    fn rejects_the_wrong_number_of_numbers() {
+        assert_rejected("lake = 16");
+        assert_rejected("forest = 64 7 3 1");
+        assert_rejected("meadow = 16 lots");
+    }
+}
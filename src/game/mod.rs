@@ -1,12 +1,12 @@
-use std::collections::{BTreeSet, BTreeMap};
-use std::collections::btree_map::Entry;
+use std::collections::BTreeSet;
+use std::fmt;
 use rand::{Rng, SeedableRng, StdRng};
 
 use ::engine::*;
 
 // TODO: how much of this stuff really need to be public?
 mod entity;
-use self::entity::{Corpse, AiState, EntityClass, EntityData};
+use self::entity::{Corpse, AiState, BusyUntil, EntityClass, EntityData};
 pub use self::entity::EntityType;
 
 mod position;
@@ -20,10 +20,47 @@ use self::fov::*;
 
 mod map;
 
+mod error;
+pub use self::error::{ActionError, ActionResult};
+
+mod projectile;
+pub use self::projectile::{ProjectileKind, ProjectileReport};
+
+mod level;
+use self::level::{CurrentLevel, GameSeed, StoredLevels, Travel, travel};
+pub use self::level::{LevelId, DEEPEST_LEVEL};
+
+mod map_config;
+pub use self::map_config::{MapGenConfig, PRESETS};
+
+mod connectivity;
+use self::connectivity::connect_level;
+pub use self::connectivity::ConnectivityReport;
+
+mod vault;
+
+mod spatial;
+use self::spatial::{TileIndex, is_adjacent, on_tile_changed};
+pub use self::spatial::Rect;
+
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
-pub struct Entity(u64);
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
 
-impl Id for Entity {}
+impl Id for Entity {
+    type Allocator = GenerationalAllocator;
+}
+
+impl GenerationalId for Entity {
+    fn from_parts(index: u32, generation: u32) -> Entity {
+        Entity { index, generation }
+    }
+
+    fn index(self) -> u32 { self.index }
+    fn generation(self) -> u32 { self.generation }
+}
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Color {
@@ -51,19 +88,86 @@ pub struct Cell {
 enum Location {
     Entity(Entity),
     Position(Position),
+    /// On a level other than the current one.
+    OtherLevel(LevelId, Position),
 }
 
 impl Component for Location {}
 
-#[derive(Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 struct Contents(BTreeSet<Entity>);
 
 impl Component for Contents {}
 
+#[derive(Debug, Clone, PartialEq)]
 struct IsPlayer;
 
 impl Component for IsPlayer {}
 
+// TODO: this is total unserializable, I'll probably have to roll my own RNG
+#[derive(Copy, Clone)]
+struct GameRng(StdRng);
+
+// Game::new reseeds this
+impl Default for GameRng {
+    fn default() -> GameRng {
+        GameRng(StdRng::from_seed(&[0]))
+    }
+}
+
+// StdRng doesn't implement Debug, and its state wouldn't be readable anyway
+impl fmt::Debug for GameRng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("GameRng(..)")
+    }
+}
+
+// Nor PartialEq. Isaac's state is private, so this compares the next few
+// numbers each would draw instead: generators in the same state always pass,
+// and different seeds agreeing on 256 bits won't happen in practice.
+impl PartialEq for GameRng {
+    fn eq(&self, other: &GameRng) -> bool {
+        let (mut a, mut b) = (self.0, other.0);
+        (0..4).all(|_| a.next_u64() == b.next_u64())
+    }
+}
+
+impl Resource for GameRng {}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct CurrentTurn(i32);
+
+impl Resource for CurrentTurn {}
+
+// Counts down after the scroll of recall is read
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct RecallTurns(Option<i32>);
+
+impl Resource for RecallTurns {}
+
+// The report on the turn being taken, filled in as it goes. Changes made
+// outside of a turn, like setting up a level, aren't reported.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PendingReport(Option<TurnReport>);
+
+impl Resource for PendingReport {}
+
+fn turn_report(w: &mut GameWorld) -> Option<&mut TurnReport> {
+    if w.resource::<PendingReport>().0.is_some() {
+        w.resource_mut::<PendingReport>().0.as_mut()
+    } else {
+        None
+    }
+}
+
+// Distance to the nearest corpse, roughly, as far as scavengers can tell.
+// Smells are replaced wholesale every turn without going through EntityMut,
+// so they're never journaled and nothing can observe them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Smell(i32);
+
+impl Component for Smell {}
+
 world! {
     GameWorld {
         Entity: {
@@ -74,16 +178,32 @@ world! {
             Damage,
             Corpse,
             AiState,
+            BusyUntil,
         }
         Position: {
-            Contents,
-            Tile,
-            IsVisible,
-            WasVisible,
+            Contents: GridStorage,
+            Tile: GridStorage,
+            IsVisible: GridStorage,
+            WasVisible: GridStorage,
+            Smell: GridStorage,
         }
     }
+    resources {
+        GameRng,
+        CurrentTurn,
+        RecallTurns,
+        PendingReport,
+        TileIndex,
+        GameSeed,
+        CurrentLevel,
+        Travel,
+        StoredLevels,
+        MapGenConfig,
+        ConnectivityReport,
+    }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Action {
     Wait,
     Move(Direction),
@@ -92,9 +212,29 @@ pub enum Action {
     GetCorpse,
     DropCorpse,
     ThrowRock(Position),
-    FireBow(Direction),
+    FireBow(Position),
+    Descend,
+    Ascend,
 }
 
+/// What happened as a result of the player's action.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TurnReport {
+    /// How many turns the action took. Nothing else moves during a free
+    /// action.
+    pub time: u32,
+    pub projectile: Option<ProjectileReport>,
+    /// Items that ended up in the player's inventory.
+    pub picked_up: Vec<EntityType>,
+    /// What each actor that died was before it became a corpse.
+    pub killed: Vec<EntityType>,
+    pub damage_taken: i8,
+    pub escaped: bool,
+}
+
+/// How far an arrow flies before falling to the ground.
+pub const BOW_RANGE: usize = 8;
+
 impl GameWorld {
     // TODO: return a ActionResult of some sort?
     fn remove_from_contents<I: Id>(&mut self, entity: Entity, location: I)
@@ -110,74 +250,141 @@ impl GameWorld {
         }
     }
 
-    fn remove_location(&mut self, id: Entity) -> ActionResult<Location>
+    fn add_to_contents<I: Id>(&mut self, entity: Entity, location: I)
+        where GameWorld: EntityComponent<I, Contents>
     {
-        let old_location = self.entity_mut(id).remove();
-        match old_location {
-            Ok(Location::Entity(e)) => { self.remove_from_contents(id, e); }
-            Ok(Location::Position(p)) => { self.remove_from_contents(id, p); }
-            Err(_) => {}
+        if let Ok(c) = self.entity_mut(location).get_or_default::<Contents>() {
+            c.0.insert(entity);
         }
-        old_location
     }
 
+    fn remove_location(&mut self, id: Entity) -> ComponentResult<Location> {
+        self.entity_mut(id).remove()
+    }
+
+    // Does nothing if `l` is inside a stale entity.
     fn set_location(&mut self, id: Entity, l: Location) -> Option<Location> {
-        let old_location = self.entity_mut(id).insert(l);
-        match old_location {
-            Some(Location::Entity(e)) => { self.remove_from_contents(id, e); }
-            Some(Location::Position(p)) => { self.remove_from_contents(id, p); }
-            None => {}
-        }
-        match l {
-            Location::Entity(e) => {
-                self.entity_mut(e).get_or_else::<Contents, _>(Default::default).0.insert(id);
-            }
-            Location::Position(p) => {
-                self.entity_mut(p).get_or_else::<Contents, _>(Default::default).0.insert(id);
+        if let Location::Entity(e) = l {
+            if !self.entity(e).is_live() {
+                return self.entity(id).get().ok().cloned();
             }
         }
-        old_location
+        self.entity_mut(id).insert(l)
+    }
+
+    fn is_player(&self, id: Entity) -> bool {
+        self.entity(id).has::<IsPlayer>()
+    }
+
+    fn register_observers(&mut self) {
+        self.observe(on_location_changed);
+        self.observe(on_damage_changed);
+        self.observe(on_entity_type_changed);
+        self.observe(on_tile_changed);
+        self.on_despawn(on_entity_despawned);
     }
 }
 
-pub struct Rect {
-    pub min_x: i32,
-    pub max_x: i32,
-    pub min_y: i32,
-    pub max_y: i32,
+// Anything the entity was carrying is dropped where it was, or despawned
+// along with it if it wasn't anywhere.
+fn on_entity_despawned(w: &mut GameWorld, id: Entity) {
+    let location = w.entity(id).get::<Location>().ok().cloned();
+    let contents = w.entity(id).get::<Contents>().map(|c| c.0.clone()).unwrap_or_default();
+    for item in contents {
+        match location {
+            Some(l) => { w.set_location(item, l); }
+            None => { w.despawn(item); }
+        }
+    }
+}
+
+// Keeps Contents in sync with Location, and notes anything that ends up in
+// the player's inventory.
+fn on_location_changed(w: &mut GameWorld, id: Entity, event: &ComponentEvent<Location>) {
+    let (old, new) = match *event {
+        ComponentEvent::Inserted(new) => (None, Some(new)),
+        ComponentEvent::Removed(old) => (Some(old), None),
+        ComponentEvent::Modified { old, new } => (Some(old), Some(new)),
+    };
+    match old {
+        Some(Location::Entity(e)) => { w.remove_from_contents(id, e); }
+        Some(Location::Position(p)) => { w.remove_from_contents(id, p); }
+        Some(Location::OtherLevel(..)) | None => {}
+    }
+    match new {
+        Some(Location::Entity(e)) => {
+            w.add_to_contents(id, e);
+            if w.is_player(e) {
+                if let Ok(&t) = w.entity(id).get::<EntityType>() {
+                    if let Some(report) = turn_report(w) {
+                        report.picked_up.push(t);
+                    }
+                }
+            }
+        }
+        Some(Location::Position(p)) => { w.add_to_contents(id, p); }
+        Some(Location::OtherLevel(..)) | None => {}
+    }
 }
 
-impl Rect {
-    fn extend(&mut self, pos: Position) {
-        self.min_x = ::std::cmp::min(self.min_x, pos.x);
-        self.max_x = ::std::cmp::max(self.max_x, pos.x);
-        self.min_y = ::std::cmp::min(self.min_y, pos.y);
-        self.max_y = ::std::cmp::max(self.max_y, pos.y);
+// Actors die once they've taken as much damage as they have health.
+fn on_damage_changed(w: &mut GameWorld, id: Entity, event: &ComponentEvent<Damage>) {
+    let total_damage = match *event {
+        ComponentEvent::Inserted(Damage(d)) | ComponentEvent::Modified { new: Damage(d), .. } => d,
+        ComponentEvent::Removed(_) => return,
+    };
+    if let Ok(&EntityClass::Actor { max_health, .. }) =
+        w.entity(id).get::<EntityType>().map(|t| &t.data().class)
+    {
+        if total_damage >= max_health {
+            w.entity_mut(id).insert(EntityType::Corpse);
+        }
     }
 }
 
-impl From<Position> for Rect {
-    fn from(pos: Position) -> Rect {
-        Rect {
-            min_x: pos.x,
-            max_x: pos.x,
-            min_y: pos.y,
-            max_y: pos.y,
+// Turning an actor into a corpse is what kills it.
+fn on_entity_type_changed(w: &mut GameWorld, id: Entity, event: &ComponentEvent<EntityType>) {
+    if let ComponentEvent::Modified { old, new: EntityType::Corpse } = *event {
+        if !old.data().is_actor() {
+            return;
+        }
+        if let Some(report) = turn_report(w) {
+            report.killed.push(old);
+        }
+        let turn_created = w.resource::<CurrentTurn>().0;
+        w.entity_mut(id).insert(Corpse {
+            turn_created,
+            original_type: old,
+        });
+        let _ = w.entity_mut(id).remove::<AiState>();
+        let _ = w.entity_mut(id).remove::<BusyUntil>();
+        // The player's damage is how we know they died
+        if !w.is_player(id) {
+            let _ = w.entity_mut(id).remove::<Damage>();
         }
     }
 }
 
 pub struct Game {
     world: GameWorld,
-    next_id: u64,
-    // TODO: this is total unserializable, I'll probably have to roll my own RNG
-    rand: StdRng,
-    recall_turns: Option<i32>,
-    // TODO: I'd like this to be a component, but then I'd need a way to swap
-    // out the entire map (or I could just be super inefficient). It's a hack
-    // either way.
-    smell_strength: BTreeMap<Position, i32>,
-    current_turn: i32,
+    systems: Schedule<Game>,
+    // Checking every action is too slow to do each frame, so it's done once
+    // a turn
+    legal_actions: Vec<Action>,
+}
+
+/// What changed between two snapshots of a game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameDiff {
+    pub entities: Vec<ComponentChange<Entity>>,
+    pub tiles: Vec<ComponentChange<Position>>,
+    pub resources: Vec<ResourceChange>,
+}
+
+impl GameDiff {
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty() && self.tiles.is_empty() && self.resources.is_empty()
+    }
 }
 
 pub struct PlayerStatus {
@@ -185,86 +392,116 @@ pub struct PlayerStatus {
     pub max_health: i8,
     pub has_bow: bool,
     pub has_sword: bool,
+    pub has_raft: bool,
     pub arrows: i32,
     pub herbs: i32,
     pub rocks: i32,
     pub corpses: i32,
     pub diamonds: i32,
     pub recall_turns: Option<i32>,
+    pub level: LevelId,
 }
 
 impl Game {
     pub fn new(seed: u64) -> Game {
+        Game::with_config(seed, MapGenConfig::default())
+    }
+
+    /// Every level of the game is generated using `config`.
+    pub fn with_config(seed: u64, config: MapGenConfig) -> Game {
         let mut g = Game {
             world: GameWorld::new(),
-            next_id: 1,
-            rand: StdRng::from_seed(&[seed as usize]),
-            recall_turns: None,
-            smell_strength: BTreeMap::new(),
-            current_turn: 0,
+            systems: Game::turn_systems(),
+            legal_actions: vec![],
         };
-        map::init_game(&mut g);
+        g.world.register_observers();
+        g.world.resource_mut::<GameSeed>().0 = seed;
+        *g.world.resource_mut::<MapGenConfig>() = config;
+        *g.rand() = StdRng::from_seed(&[seed as usize]);
+        map::init_level(&mut g, LevelId(0));
         update_fov(&mut g);
+        g.legal_actions = g.find_legal_actions();
         g
     }
 
-    pub fn take_turn(&mut self, action: Action) -> ActionResult<()> {
-        let player = self.player()?;
-        let player_pos = self.player_position()?;
-
-        // TODO: only abort rest of turn if uncommitted
-        match action {
-            Action::Wait => {}
-            Action::Move(dir) => {
-                self.move_entity(player, dir)?;
-                // TODO: really ignore result?
-                let _ = self.auto_pickup();
-            }
-            Action::EatHerb => {
-                self.consume_item(EntityType::Herb)?;
-                self.add_damage(player, -1);
+    /// Failed actions are rolled back and don't use up any time.
+    pub fn take_turn(&mut self, action: Action) -> ActionResult<TurnReport> {
+        let health_before = self.player_status().map(|s| s.health);
+
+        let savepoint = self.begin(CheckGuard::Uncommitted);
+        self.world.resource_mut::<PendingReport>().0 = Some(TurnReport::default());
+        let time = match self.perform(action) {
+            Ok(time) => time,
+            Err(e) => {
+                self.world.rollback(savepoint);
+                return Err(e);
             }
-            Action::ReadScroll => {
-                self.recall_turns = Some(self.rand.gen_range(20, 30));
-            }
-            Action::GetCorpse => {
-                let corpse = self.find_corpse()?;
-                self.world.set_location(corpse, Location::Entity(player));
-            }
-            Action::DropCorpse => {
-                let corpse = self.find_item(EntityType::Corpse)?;
-                self.world.set_location(corpse, Location::Position(player_pos));
-            }
-            Action::ThrowRock(pos) => {
-                let &IsVisible(dist) = self.world.entity(pos).get()?;
-                if dist > self.player_fov_range() {
-                    return self.world.err();
-                }
-                let rock = self.find_item(EntityType::Rock)?;
-                self.attack_position(player, pos, 1)?;
-                // TODO: do something with ActionResult?
-                let _ = self.world.remove_location(rock);
-            }
-            Action::FireBow(dir) => {
-                self.find_item(EntityType::Bow)?;
-                self.consume_item(EntityType::Arrow)?;
-                let mut pos = player_pos;
-                for _ in 0..8 {
-                    pos = pos.step(dir);
-                    if self.get_tile(pos).is_obstructed() {
-                        break;
-                    }
-                    if self.attack_position(player, pos, 2).is_ok() {
-                        break;
-                    }
-                }
-            }
-        }
+        };
+        self.world.commit(savepoint);
 
         update_fov(self);
+        for _ in 0..time {
+            self.pass_turn();
+        }
+
+        #[cfg(debug_assertions)]
+        {
+            let leaked = self.leaked_entities();
+            assert!(leaked.is_empty(), "leaked entities: {:?}", leaked);
+        }
+
+        let mut report = self.world.resource_mut::<PendingReport>().0.take().unwrap_or_default();
+        report.time = time;
+        if let (Some(before), Some(after)) = (health_before, self.player_status().map(|s| s.health)) {
+            report.damage_taken = before - after;
+        }
+        self.legal_actions = self.find_legal_actions();
+        Ok(report)
+    }
+
+    /// Everything that happens each turn after the player acts, in order.
+    fn turn_systems() -> Schedule<Game> {
+        let mut systems = Schedule::new(&["travel", "creatures", "upkeep", "senses", "end"]);
+        systems.add("travel", "travel", &[], travel);
+        systems.add("ai", "creatures", &[], Game::run_ai);
+        systems.add("recall", "upkeep", &[], Game::count_down_recall);
+        systems.add("fov", "senses", &[], update_fov);
+        systems.add("smells", "senses", &["fov"], Game::update_smells);
+        systems.add("advance_turn", "end", &[], Game::advance_turn);
+        systems
+    }
 
-        let creatures: Vec<Entity> = self.world.component::<AiState>().ids().collect();
+    fn pass_turn(&mut self) {
+        // The systems need the whole game, so they're taken out while they run
+        let mut systems = ::std::mem::take(&mut self.systems);
+        systems.run(self);
+        self.systems = systems;
+    }
+
+    /// How well connected the most recently generated level's map is.
+    pub fn connectivity(&self) -> &ConnectivityReport {
+        self.world.resource()
+    }
+
+    /// How long each turn system has taken so far, in the order they run.
+    pub fn system_timings(&self) -> Vec<SystemTiming> {
+        self.systems.timings()
+    }
+
+    fn run_ai(&mut self) {
+        // Creatures on other levels wait for the player to come back
+        let creatures: Vec<Entity> = self.world.query::<(&AiState, &Location)>()
+            .filter(|&(_, (_, location))| matches!(*location, Location::Position(_)))
+            .map(|(id, _)| id)
+            .collect();
+        let turn = self.current_turn();
         for id in creatures {
+            // Still wading into a bog
+            if let Ok(&BusyUntil(until)) = self.world.entity(id).get() {
+                if turn < until {
+                    continue;
+                }
+            }
             if let Ok(&state) = self.world.entity(id).get::<AiState>() {
                 let new_state = state.take_turn(self, id);
                 if let Ok(state_mut) = self.world.entity_mut(id).get_mut::<AiState>() {
@@ -272,20 +509,63 @@ impl Game {
                 }
             }
         }
+    }
 
-        if let Some(turns) = self.recall_turns {
-            self.recall_turns = Some(turns - 1);
-            if self.recall_turns == Some(0) {
-                // TODO: really ignore result?
-                let _ = self.world.remove_location(player);
+    fn count_down_recall(&mut self) {
+        if let Some(turns) = self.world.resource::<RecallTurns>().0 {
+            self.world.resource_mut::<RecallTurns>().0 = Some(turns - 1);
+            if turns == 1 {
+                if let Ok(player) = self.player() {
+                    let escaped = self.world.remove_location(player).is_ok();
+                    if let Some(report) = turn_report(&mut self.world) {
+                        report.escaped = escaped;
+                    }
+                }
             }
         }
+    }
 
-        update_fov(self);
-        self.update_smells();
-        self.current_turn += 1;
+    fn advance_turn(&mut self) {
+        self.world.resource_mut::<CurrentTurn>().0 += 1;
+    }
 
-        Ok(())
+    /// Checks whether `action` would succeed right now, without changing
+    /// anything. If it wouldn't, the error says why.
+    pub fn check_action(&mut self, action: Action) -> ActionResult<()> {
+        let savepoint = self.begin(CheckGuard::CheckOnly);
+        let result = self.perform(action).map(|_| ());
+        self.world.rollback(savepoint);
+        result
+    }
+
+    /// Every action the player could take right now. Rocks and arrows are
+    /// aimed at each visible actor and each adjacent tile.
+    pub fn legal_actions(&self) -> &[Action] {
+        &self.legal_actions
+    }
+
+    fn find_legal_actions(&mut self) -> Vec<Action> {
+        let player_pos = match self.player_position() {
+            Ok(pos) => pos,
+            Err(_) => return vec![],
+        };
+        let mut targets: Vec<_> = self.visible_actors().into_iter().map(|(_, pos)| pos).collect();
+        targets.extend(ALL_DIRECTIONS.iter().map(|&dir| player_pos.step(dir)));
+
+        let mut candidates = vec![
+            Action::Wait,
+            Action::EatHerb,
+            Action::ReadScroll,
+            Action::GetCorpse,
+            Action::DropCorpse,
+            Action::Descend,
+            Action::Ascend,
+        ];
+        candidates.extend(ALL_DIRECTIONS.iter().map(|&dir| Action::Move(dir)));
+        candidates.extend(targets.iter().map(|&pos| Action::ThrowRock(pos)));
+        candidates.extend(targets.iter().map(|&pos| Action::FireBow(pos)));
+
+        candidates.into_iter().filter(|&action| self.check_action(action).is_ok()).collect()
     }
 
     pub fn render(&self, pos: Position) -> Cell {
@@ -306,7 +586,7 @@ impl Game {
 
                 let mut data: Option<&'static EntityData> = None;
 
-                if let Ok(&Contents(ref entities)) = self.world.entity(pos).get() {
+                if let Ok(Contents(entities)) = self.world.entity(pos).get() {
                     for &e in entities {
                         if let Ok(entity_data) = self.world.entity(e)
                             .get::<EntityType>().map(|t| t.data())
@@ -336,7 +616,7 @@ impl Game {
 
         // // Visualize smell propagation
         // if "\".,=".contains(cell.ch) {
-        //     if let Some(&s) = self.smell_strength.get(&pos) {
+        //     if let Ok(&Smell(s)) = self.world.entity(pos).get() {
         //         if s > 0 && s <= 26 {
         //             cell.ch = (64 + s) as u8 as char;
         //         }
@@ -354,16 +634,18 @@ impl Game {
             if let EntityClass::Actor { max_health, .. } = EntityType::Player.data().class {
                 let damage = player_ref.get::<Damage>().map(|d| d.0).unwrap_or(0);
                 return Some(PlayerStatus {
-                    max_health: max_health,
+                    max_health,
                     health: max_health - damage,
                     has_bow: self.inventory_count(EntityType::Bow) > 0,
                     has_sword: self.inventory_count(EntityType::Sword) > 0,
+                    has_raft: self.inventory_count(EntityType::Raft) > 0,
                     arrows: self.inventory_count(EntityType::Arrow),
                     herbs: self.inventory_count(EntityType::Herb),
                     rocks: self.inventory_count(EntityType::Rock),
                     corpses: self.inventory_count(EntityType::Corpse),
                     diamonds: self.inventory_count(EntityType::Diamond),
-                    recall_turns: self.recall_turns,
+                    recall_turns: self.world.resource::<RecallTurns>().0,
+                    level: self.world.resource::<CurrentLevel>().0,
                 });
             }
         }
@@ -378,17 +660,220 @@ impl Game {
         self.player_position().map(|pos| {
             let fov_range = self.player_fov_range();
             let mut rect = Rect::from(pos);
-            for (pos, &IsVisible(dist)) in self.world.component::<IsVisible>().iter() {
-                if dist <= fov_range {
-                    rect.extend(pos);
+            // Nothing further away than that in any direction can be in view
+            for pos in Rect::around(pos, fov_range as i32).positions() {
+                if let Ok(&IsVisible(dist)) = self.world.entity(pos).get() {
+                    if dist <= fov_range {
+                        rect.extend(pos);
+                    }
                 }
             }
             rect
         })
     }
+
+    /// Actors other than the player within view, nearest first.
+    pub fn visible_actors(&self) -> Vec<(Entity, Position)> {
+        let player_pos = match self.player_position() {
+            Ok(pos) => pos,
+            Err(_) => return vec![],
+        };
+        let fov_range = self.player_fov_range();
+        self.entities_within(player_pos, fov_range as i32).into_iter()
+            .filter(|&(_, pos)| {
+                self.world.entity(pos).get::<IsVisible>().map(|v| v.0 <= fov_range).unwrap_or(false)
+            })
+            .filter(|&(id, _)| self.is_live_actor(id) && !self.is_player(id))
+            .collect()
+    }
+
+    /// The tiles a projectile aimed at `target` would pass through, assuming
+    /// it hits the first actor in its way.
+    pub fn projectile_path(&self, kind: ProjectileKind, target: Position) -> Vec<Position> {
+        match self.player_position() {
+            Ok(pos) => projectile::preview(self, pos, kind, target),
+            Err(_) => vec![],
+        }
+    }
+
+    /// A copy of the whole game, to go back to or diff against later.
+    pub fn snapshot(&self) -> Game {
+        Game {
+            world: self.world.snapshot(),
+            systems: self.systems.clone(),
+            legal_actions: self.legal_actions.clone(),
+        }
+    }
+
+    /// Everything that differs in `newer`, compared to this game.
+    pub fn diff(&self, newer: &Game) -> GameDiff {
+        GameDiff {
+            entities: ::engine::diff(&self.world, &newer.world),
+            tiles: ::engine::diff(&self.world, &newer.world),
+            resources: ::engine::diff_resources(&self.world, &newer.world),
+        }
+    }
+
+    /// Debug listing of every component on a tile, then everything on it
+    /// (and inside that), then the game's resources.
+    pub fn inspect(&self, pos: Position) -> Vec<String> {
+        let mut lines = vec![format!("{:?}", pos)];
+        for dump in ::engine::inspect(&self.world, pos) {
+            lines.push(format!("  {}: {}", dump.name, dump.value));
+        }
+        if let Ok(Contents(entities)) = self.world.entity(pos).get() {
+            for &id in entities {
+                self.inspect_entity(id, 1, &mut lines);
+            }
+        }
+        lines.push(String::from("Resources"));
+        for dump in ::engine::inspect_resources(&self.world) {
+            lines.push(format!("  {}: {}", dump.name, dump.value));
+        }
+        lines
+    }
+
+    fn inspect_entity(&self, id: Entity, depth: usize, lines: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        lines.push(format!("{}{:?}", indent, id));
+        for dump in ::engine::inspect(&self.world, id) {
+            lines.push(format!("{}  {}: {}", indent, dump.name, dump.value));
+        }
+        if let Ok(Contents(entities)) = self.world.entity(id).get() {
+            for &item in entities {
+                self.inspect_entity(item, depth + 1, lines);
+            }
+        }
+    }
+
+    /// Whether the player could shoot `kind` at `target` right now.
+    pub fn in_range(&self, kind: ProjectileKind, target: Position) -> bool {
+        let has_ammo = self.inventory_count(kind.item()) > 0;
+        let has_launcher = kind != ProjectileKind::Arrow
+            || self.inventory_count(EntityType::Bow) > 0;
+        let is_visible = kind != ProjectileKind::Rock
+            || self.world.entity(target).get::<IsVisible>()
+                .map(|&IsVisible(dist)| dist <= self.player_fov_range())
+                .unwrap_or(false);
+        has_ammo && has_launcher && is_visible
+            && self.projectile_path(kind, target).contains(&target)
+    }
 }
 
 impl Game {
+    // Game state all lives in the world's components and resources, so
+    // rolling back the world's journal undoes everything.
+    fn begin(&mut self, guard: CheckGuard) -> Savepoint {
+        let savepoint = self.world.begin(guard);
+        // The RNG is too big to journal on every draw, so it's journaled once
+        // here, and rand doesn't go through resource_mut
+        let rng = *self.world.resource::<GameRng>();
+        self.world.journal_mut().record(Box::new(move |w: &mut GameWorld| {
+            *w.borrow_resource_mut() = rng;
+        }));
+        savepoint
+    }
+
+    fn rand(&mut self) -> &mut StdRng {
+        &mut HasResource::<GameRng>::borrow_resource_mut(&mut self.world).0
+    }
+
+    fn current_turn(&self) -> i32 {
+        self.world.resource::<CurrentTurn>().0
+    }
+
+    // Returns how many turns the action took.
+    fn perform(&mut self, action: Action) -> ActionResult<u32> {
+        let player = self.player().map_err(|_| ActionError::NoPlayer)?;
+        let player_pos = self.player_position().map_err(|_| ActionError::NoPlayer)?;
+        if !self.is_live_actor(player) {
+            return Err(ActionError::PlayerDead);
+        }
+
+        let mut time = 1;
+        match action {
+            Action::Wait => {}
+            Action::Move(dir) => {
+                self.move_entity(player, dir)?;
+                // Bumping into something to attack it doesn't go anywhere
+                let new_pos = player_pos.step(dir);
+                if self.player_position().ok() == Some(new_pos) {
+                    time = self.get_tile(new_pos).move_time();
+                }
+                // TODO: really ignore result?
+                let _ = self.auto_pickup();
+            }
+            Action::EatHerb => {
+                if self.world.entity(player).get::<Damage>().map(|d| d.0).unwrap_or(0) == 0 {
+                    return Err(ActionError::FullHealth);
+                }
+                self.consume_item(EntityType::Herb)?;
+                self.add_damage(player, -1);
+            }
+            Action::ReadScroll => {
+                if self.world.resource::<RecallTurns>().0.is_some() {
+                    return Err(ActionError::AlreadyRecalling);
+                }
+                let turns = self.rand().gen_range(20, 30);
+                self.world.resource_mut::<RecallTurns>().0 = Some(turns);
+            }
+            Action::GetCorpse => {
+                let corpse = self.find_corpse().map_err(|_| ActionError::NoCorpseHere)?;
+                self.world.set_location(corpse, Location::Entity(player));
+            }
+            Action::DropCorpse => {
+                let corpse = self.require_item(EntityType::Corpse)?;
+                self.world.set_location(corpse, Location::Position(player_pos));
+            }
+            Action::ThrowRock(pos) => {
+                let rock = self.require_item(EntityType::Rock)?;
+                if pos == player_pos {
+                    return Err(ActionError::TargetIsSelf);
+                }
+                let in_view = self.world.entity(pos).get::<IsVisible>()
+                    .map(|&IsVisible(dist)| dist <= self.player_fov_range())
+                    .unwrap_or(false);
+                if !in_view {
+                    return Err(ActionError::OutOfSight(pos));
+                }
+                let report = projectile::fire(self, player, rock, ProjectileKind::Rock, pos)?;
+                if let Some(turn) = turn_report(&mut self.world) {
+                    turn.projectile = Some(report);
+                }
+            }
+            Action::FireBow(target) => {
+                self.require_item(EntityType::Bow)?;
+                let arrow = self.require_item(EntityType::Arrow)?;
+                if target == player_pos {
+                    return Err(ActionError::TargetIsSelf);
+                }
+                let report = projectile::fire(self, player, arrow, ProjectileKind::Arrow, target)?;
+                if let Some(turn) = turn_report(&mut self.world) {
+                    turn.projectile = Some(report);
+                }
+            }
+            Action::Descend => {
+                if self.get_tile(player_pos) != Tile::StairsDown {
+                    return Err(ActionError::NoStairsDown);
+                }
+                let level = self.world.resource::<CurrentLevel>().0;
+                self.world.resource_mut::<Travel>().0 = Some(LevelId(level.0 + 1));
+            }
+            Action::Ascend => {
+                if self.get_tile(player_pos) != Tile::StairsUp {
+                    return Err(ActionError::NoStairsUp);
+                }
+                let level = self.world.resource::<CurrentLevel>().0;
+                self.world.resource_mut::<Travel>().0 = Some(LevelId(level.0 - 1));
+            }
+        }
+        Ok(time)
+    }
+
+    fn require_item(&self, t: EntityType) -> ActionResult<Entity> {
+        self.find_item(t).map_err(|_| ActionError::MissingItem(t))
+    }
+
     fn player(&self) -> QueryResult<Entity> {
         // TODO: make sure there is at most one player?
         self.world.component::<IsPlayer>().ids().next().ok_or(())
@@ -404,7 +889,7 @@ impl Game {
             .filter(
                 |&id| self.world.entity(id).get().map(
                     |entity_type| [
-                        EntityType::Sword, EntityType::Bow,
+                        EntityType::Sword, EntityType::Bow, EntityType::Raft,
                         EntityType::Arrow, EntityType::Rock,
                         EntityType::Herb, EntityType::Diamond,
                     ].contains(entity_type)
@@ -448,11 +933,13 @@ impl Game {
     }
 
     fn move_entity(&mut self, id: Entity, dir: Direction) -> ActionResult<()> {
-        let pos = self.entity_position(id)?;
+        let pos = self.entity_position(id).map_err(|_| ActionError::Unplaced(id))?;
         let new_pos = pos.step(dir);
         // TODO: allow attacking enemies on unwalkable tiles?
-        if !self.get_tile(new_pos).is_walkable() {
-            return self.world.err();
+        let tile = self.get_tile(new_pos);
+        let rafting = tile == Tile::DeepWater && self.is_player(id) && self.find_item(EntityType::Raft).is_ok();
+        if !tile.is_walkable() && !rafting {
+            return Err(ActionError::Blocked(new_pos));
         }
         if let Ok(target) = self.get_actor_by_position(new_pos) {
             self.bump_attack(id, target)
@@ -468,70 +955,51 @@ impl Game {
     }
 
     fn bump_damage(&mut self, attacker: Entity) -> ActionResult<i8> {
-        let actor_type = self.world.entity(attacker).get::<EntityType>()?;
-        if let EntityClass::Actor { damage, .. } = actor_type.data().class {
+        let actor_class = self.world.entity(attacker).get::<EntityType>()
+            .map(|t| &t.data().class);
+        if let Ok(&EntityClass::Actor { damage, .. }) = actor_class {
             Ok(if self.is_player(attacker) && self.find_item(EntityType::Sword).is_ok() {
                 3
             } else {
                 damage
             })
         } else {
-            self.world.err()
+            Err(ActionError::NotAnActor(attacker))
         }
     }
 
-    fn attack_position(&mut self, attacker: Entity, pos: Position, damage: i8) -> ActionResult<()> {
-        let target = self.get_actor_by_position(pos)?;
-        self.attack_entity(attacker, target, damage)
-    }
-
     fn attack_entity(&mut self, attacker: Entity, target: Entity, damage: i8) -> ActionResult<()> {
         if self.is_player(attacker) == self.is_player(target) {
-            self.world.err()
+            Err(ActionError::FriendlyTarget(target))
         } else {
             self.add_damage(target, damage);
             Ok(())
         }
     }
 
+    fn is_live_actor(&self, id: Entity) -> bool {
+        self.world.entity(id).get::<EntityType>()
+            .map(|t| t.data().is_actor())
+            .unwrap_or(false)
+    }
+
     fn is_player(&self, id: Entity) -> bool {
-        self.world.entity(id).get::<IsPlayer>().is_ok()
+        self.world.is_player(id)
     }
 
     // TODO: make this return a ActionResult of some sort?
+    // Killing the target is up to on_damage_changed.
     fn add_damage(&mut self, target: Entity, damage: i8) {
         // TODO: add helpers for getting entities as actors
-        if let Ok(&EntityClass::Actor { max_health, .. }) =
-            self.world.entity(target).get::<EntityType>().map(|t| &t.data().class)
-        {
-            let total_damage = {
-                let mut target_entity = self.world.entity_mut(target);
-                let target_damage: &mut Damage = target_entity.get_or_default();
-                target_damage.0 += damage;
-                if target_damage.0 < 0 { target_damage.0 = 0; }
-                target_damage.0
-            };
-            if total_damage >= max_health {
-                self.kill_entity(target);
-            }
-        }
-    }
-
-    // TODO: make this return a ActionResult of some sort?
-    fn kill_entity(&mut self, id: Entity) {
-        let old_type = self.world.entity_mut(id).insert(EntityType::Corpse);
-        if let Some(corpse_type) = old_type {
-            self.world.entity_mut(id).insert(Corpse {
-                turn_created: self.current_turn,
-                original_type: corpse_type,
-            });
+        if self.is_live_actor(target) {
+            let old_damage = self.world.entity(target).get::<Damage>().map(|d| d.0).unwrap_or(0);
+            let total_damage = ::std::cmp::max(old_damage + damage, 0);
+            self.world.entity_mut(target).insert(Damage(total_damage));
         }
-        let _ = self.world.entity_mut(id).remove::<AiState>();
     }
 
     fn put_entity(&mut self, t: EntityType, p: Position) {
-        let id = Entity(self.next_id);
-        self.next_id += 1;
+        let id = self.world.spawn();
         if t == EntityType::Player {
             self.world.entity_mut(id).insert(IsPlayer);
         } else if t.data().is_actor() {
@@ -544,7 +1012,7 @@ impl Game {
     fn inventory_count(&self, t: EntityType) -> i32 {
         let mut count = 0;
         if let Ok(player) = self.player() {
-            if let Ok(&Contents(ref inventory)) = self.world.entity(player).get() {
+            if let Ok(Contents(inventory)) = self.world.entity(player).get() {
                 for &item_id in inventory {
                     if self.world.entity(item_id).get() == Ok(&t) {
                         count += 1;
@@ -557,7 +1025,7 @@ impl Game {
 
     fn find_item(&self, t: EntityType) -> QueryResult<Entity> {
         let player = self.player()?;
-        let &Contents(ref inventory) = self.world.entity(player).get()?;
+        let Contents(inventory) = self.world.entity(player).get()?;
         for &item_id in inventory {
             if self.world.entity(item_id).get() == Ok(&t) {
                 return Ok(item_id);
@@ -568,7 +1036,7 @@ impl Game {
 
     fn find_corpse(&self) -> QueryResult<Entity> {
         let pos = self.player_position()?;
-        let &Contents(ref contents) = self.world.entity(pos).get()?;
+        let Contents(contents) = self.world.entity(pos).get()?;
         for &id in contents {
             if self.world.entity(id).get() == Ok(&EntityType::Corpse) {
                 return Ok(id);
@@ -578,67 +1046,261 @@ impl Game {
     }
 
     fn consume_item(&mut self, t: EntityType) -> ActionResult<()> {
-        let item = self.find_item(t)?;
-        // TODO: really ignore result?
-        // TODO: destroy item too?
-        let _ = self.world.remove_location(item);
+        let item = self.require_item(t)?;
+        self.world.despawn(item);
         Ok(())
     }
 
+    /// Entities that have components but no `EntityType`, which means
+    /// something was only partially removed instead of being despawned.
+    #[cfg(debug_assertions)]
+    fn leaked_entities(&self) -> Vec<Entity> {
+        EntityStorage::<Entity>::ids(&self.world).into_iter()
+            .filter(|&id| !self.world.entity(id).has::<EntityType>())
+            .collect()
+    }
+
     // TODO: make this return a ActionResult of some sort?
+    // Smells are recomputed all at once outside of any transaction, so the
+    // new ones are swapped straight into the storage. Inserting them one at
+    // a time through EntityMut would do nothing but slow this down.
     fn update_smells(&mut self) {
-        let mut updated_smells: BTreeMap<_, _> =
-            self.world.component::<Tile>().iter().filter_map(|(pos, &tile)| {
-                match tile {
-                    Tile::Wall => None,
-                    Tile::ShallowWater | Tile::DeepWater => Some((pos, 1)),
-                    _ => Some((pos, 2)),
-                }
-            }).map(|(pos, n)| {
-                let mut rand = self.rand;
-                let new_strength = (0..n).filter_map(|_| {
-                    let &dir = rand.choose(&ALL_DIRECTIONS).unwrap();
-                    self.smell_strength.get(&pos.step(dir))
-                }).map(Clone::clone)
-                    .min().unwrap_or(::std::i32::MAX)
-                    .saturating_add(1);
-                (pos, new_strength)
-            }).collect();
+        debug_assert!(!self.world.journal().is_recording(), "smells can't be rolled back");
+        let mut updated_smells = GridStorage::new();
+        for (pos, &tile) in self.world.component::<Tile>().iter() {
+            let n = match tile {
+                Tile::Wall => continue,
+                Tile::ShallowWater | Tile::DeepWater => 1,
+                _ => 2,
+            };
+            let mut rand = self.world.resource::<GameRng>().0;
+            let new_strength = (0..n).filter_map(|_| {
+                let &dir = rand.choose(&ALL_DIRECTIONS).unwrap();
+                self.world.entity(pos.step(dir)).get::<Smell>().ok()
+            }).map(|&Smell(s)| s)
+                .min().unwrap_or(i32::MAX)
+                .saturating_add(1);
+            updated_smells.insert(pos, Smell(new_strength));
+        }
 
         // TODO: Find some way to make stacks of corpses smell more
-        for (id, &Corpse { turn_created, .. }) in self.world.component::<Corpse>().iter() {
+        for (id, (&Corpse { turn_created, .. }, _)) in self.world.query::<(&Corpse, &Location)>() {
             // TODO: really ignore errors?
             let _ = self.locate_entity(id).map(|pos| {
-                let strength = (self.current_turn - turn_created) / 20;
-                match updated_smells.entry(pos) {
-                    Entry::Vacant(entry) => {
-                        entry.insert(strength);
-                    }
-                    Entry::Occupied(mut entry) => {
-                        let strength = ::std::cmp::min(*entry.get(), strength);
-                        entry.insert(strength);
-                    }
-                }
+                let strength = (self.current_turn() - turn_created) / 20;
+                let smell = updated_smells.get_or_else(pos, || Smell(strength));
+                smell.0 = ::std::cmp::min(smell.0, strength);
             });
         }
 
-        self.smell_strength = updated_smells;
+        ::std::mem::swap(self.world.component_mut::<Smell>(), &mut updated_smells);
     }
 
     fn locate_entity(&self, mut id: Entity) -> QueryResult<Position> {
         for _ in 0..32 { // TODO: actual cycle detection?
-            match self.world.entity(id).get()? {
-                &Location::Entity(e) => { id = e; }
-                &Location::Position(p) => { return Ok(p); }
+            match *self.world.entity(id).get()? {
+                Location::Entity(e) => { id = e; }
+                Location::Position(p) => { return Ok(p); }
+                Location::OtherLevel(..) => { return Err(()); }
             }
         }
         Err(())
     }
 
     fn entity_position(&self, id: Entity) -> QueryResult<Position> {
-        match self.world.entity(id).get()? {
-            &Location::Entity(_) => Err(()),
-            &Location::Position(p) => Ok(p),
+        match *self.world.entity(id).get()? {
+            Location::Entity(_) | Location::OtherLevel(..) => Err(()),
+            Location::Position(p) => Ok(p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub const CENTER: Position = Position { x: 0, y: 0 };
+
+    /// A square of ground `radius` tiles out from the player, and nothing
+    /// else. Creatures and items are up to each test.
+    pub fn field(radius: i32) -> Game {
+        let mut g = Game {
+            world: GameWorld::new(),
+            systems: Game::turn_systems(),
+            legal_actions: vec![],
+        };
+        g.world.register_observers();
+        for pos in Rect::around(CENTER, radius).positions() {
+            g.world.entity_mut(pos).insert(Tile::Ground);
         }
+        g.put_entity(EntityType::Player, CENTER);
+        update_fov(&mut g);
+        g
+    }
+
+    pub fn give(g: &mut Game, t: EntityType) -> Entity {
+        let player = g.player().unwrap();
+        let id = g.world.spawn();
+        g.world.entity_mut(id).insert(t);
+        g.world.set_location(id, Location::Entity(player));
+        id
+    }
+
+    fn east() -> Position {
+        CENTER.step(Direction::East)
+    }
+
+    #[test]
+    fn failures_say_why() {
+        let mut g = field(3);
+        g.world.entity_mut(east()).insert(Tile::Tree);
+        assert_eq!(g.check_action(Action::Move(Direction::East)), Err(ActionError::Blocked(east())));
+        assert_eq!(g.check_action(Action::EatHerb), Err(ActionError::FullHealth));
+        assert_eq!(g.check_action(Action::GetCorpse), Err(ActionError::NoCorpseHere));
+        assert_eq!(g.check_action(Action::DropCorpse),
+            Err(ActionError::MissingItem(EntityType::Corpse)));
+        assert_eq!(g.check_action(Action::Descend), Err(ActionError::NoStairsDown));
+        assert_eq!(g.check_action(Action::Ascend), Err(ActionError::NoStairsUp));
+        assert_eq!(g.check_action(Action::ThrowRock(east())),
+            Err(ActionError::MissingItem(EntityType::Rock)));
+        assert_eq!(g.check_action(Action::FireBow(east())),
+            Err(ActionError::MissingItem(EntityType::Bow)));
+    }
+
+    #[test]
+    fn item_failures_say_why() {
+        let mut g = field(3);
+        give(&mut g, EntityType::Bow);
+        assert_eq!(g.check_action(Action::FireBow(east())),
+            Err(ActionError::MissingItem(EntityType::Arrow)));
+        give(&mut g, EntityType::Arrow);
+        assert_eq!(g.check_action(Action::FireBow(CENTER)), Err(ActionError::TargetIsSelf));
+
+        give(&mut g, EntityType::Rock);
+        assert_eq!(g.check_action(Action::ThrowRock(CENTER)), Err(ActionError::TargetIsSelf));
+        let far = Position { x: 50, y: 0 };
+        assert_eq!(g.check_action(Action::ThrowRock(far)), Err(ActionError::OutOfSight(far)));
+
+        g.take_turn(Action::ReadScroll).unwrap();
+        assert_eq!(g.check_action(Action::ReadScroll), Err(ActionError::AlreadyRecalling));
+    }
+
+    #[test]
+    fn actions_report_their_time() {
+        let mut g = field(3);
+        g.world.entity_mut(east()).insert(Tile::Bog);
+        assert_eq!(g.take_turn(Action::Wait).map(|r| r.time), Ok(1));
+        assert_eq!(g.current_turn(), 1);
+        assert_eq!(g.take_turn(Action::Move(Direction::East)).map(|r| r.time), Ok(2));
+        assert_eq!(g.current_turn(), 3);
+
+        give(&mut g, EntityType::Corpse);
+        assert_eq!(g.take_turn(Action::DropCorpse).map(|r| r.time), Ok(1));
+        assert_eq!(g.current_turn(), 4);
+        assert_eq!(g.take_turn(Action::GetCorpse).map(|r| r.time), Ok(1));
+    }
+
+    #[test]
+    fn visible_actors_are_nearest_first() {
+        let mut g = field(4);
+        let (far, near) = (Position { x: -3, y: 0 }, Position { x: 1, y: 1 });
+        g.put_entity(EntityType::Rat, far);
+        g.put_entity(EntityType::Wolf, near);
+        g.put_entity(EntityType::Deer, Position { x: 9, y: 9 });
+        update_fov(&mut g);
+        let actors: Vec<_> = g.visible_actors().into_iter().map(|(_, pos)| pos).collect();
+        assert_eq!(actors, vec![near, far]);
+    }
+
+    #[test]
+    fn stairs_are_cut_into_the_cliffs() {
+        for seed in 0..4 {
+            let g = Game::new(seed);
+            let stairs: Vec<_> = g.world.component::<Tile>().iter()
+                .filter(|&(_, &t)| t == Tile::StairsDown)
+                .map(|(pos, _)| pos)
+                .collect();
+            assert_eq!(stairs.len(), 1);
+            assert!(ORTHOGONAL_DIRECTIONS.iter().any(|&d| g.get_tile(stairs[0].step(d)) == Tile::Wall));
+        }
+    }
+
+    #[test]
+    fn despawning_drops_what_was_carried() {
+        let mut g = field(3);
+        let player = g.player().unwrap();
+        let rock = give(&mut g, EntityType::Rock);
+        g.world.despawn(player);
+        assert_eq!(g.world.entity(rock).get(), Ok(&Location::Position(CENTER)));
+        assert_eq!(g.world.entity(CENTER).get::<Contents>().map(|c| c.0.iter().cloned().collect()),
+            Ok(vec![rock]));
+        assert!(g.leaked_entities().is_empty());
+    }
+
+    #[test]
+    fn rollback_restores_the_rng() {
+        let mut g = field(3);
+        let mut expected = g.world.resource::<GameRng>().0;
+        // Reading the scroll draws how long the recall takes
+        assert!(g.check_action(Action::ReadScroll).is_ok());
+        assert_eq!(g.rand().next_u64(), expected.next_u64());
+    }
+
+
+    /// The names of every component and resource that `d` touches.
+    fn changed(d: &GameDiff) -> Vec<&'static str> {
+        let mut names: Vec<_> = d.entities.iter().map(|c| c.component)
+            .chain(d.tiles.iter().map(|c| c.component))
+            .chain(d.resources.iter().map(|c| c.resource))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    #[test]
+    fn waiting_only_passes_time() {
+        let mut g = field(3);
+        let before = g.snapshot();
+        g.take_turn(Action::Wait).unwrap();
+        assert_eq!(changed(&before.diff(&g)), vec!["CurrentTurn", "Smell"]);
+    }
+
+    #[test]
+    fn moving_changes_only_the_player_and_the_view() {
+        let mut g = field(3);
+        let player = g.player().unwrap();
+        let before = g.snapshot();
+        g.take_turn(Action::Move(Direction::East)).unwrap();
+        let diff = before.diff(&g);
+        assert_eq!(changed(&diff),
+            vec!["Contents", "CurrentTurn", "IsVisible", "Location", "Smell", "WasVisible"]);
+        assert!(diff.entities.iter().all(|c| c.id == player));
+        assert!(diff.tiles.iter().filter(|c| c.component == "Contents")
+            .all(|c| c.id == CENTER || c.id == east()));
+    }
+
+    #[test]
+    fn throwing_changes_only_the_rock() {
+        let mut g = field(3);
+        let player = g.player().unwrap();
+        let rock = give(&mut g, EntityType::Rock);
+        let target = Position { x: 3, y: 0 };
+        let before = g.snapshot();
+        g.take_turn(Action::ThrowRock(target)).unwrap();
+        let diff = before.diff(&g);
+        assert_eq!(changed(&diff),
+            vec!["Contents", "CurrentTurn", "Location", "Smell"]);
+        assert!(diff.entities.iter().all(|c| c.id == player || c.id == rock));
+        assert!(diff.tiles.iter().filter(|c| c.component == "Contents").all(|c| c.id == target));
+        assert_eq!(g.world.entity(rock).get::<Location>(), Ok(&Location::Position(target)));
+    }
+
+    #[test]
+    fn failures_change_nothing() {
+        let mut g = field(3);
+        let before = g.snapshot();
+        assert!(g.take_turn(Action::EatHerb).is_err());
+        assert!(before.diff(&g).is_empty());
     }
 }
@@ -11,11 +11,32 @@ pub enum Tile {
     ShortGrass,
     LongGrass,
     Tree,
+    StairsDown,
+    StairsUp,
+    Rock,
+    CaveFloor,
+    StoneWall,
+    Rubble,
+    Boulder,
+    Bog,
+    Bridge,
 }
 
 impl Component for Tile {}
 
 impl Tile {
+    /// Looks a tile up by its variant name, ignoring case and underscores, so
+    /// `long_grass` is `LongGrass`.
+    pub fn from_name(name: &str) -> Option<Tile> {
+        let name = name.replace('_', "");
+        [
+            Tile::Wall, Tile::BoringGround, Tile::Ground, Tile::ShallowWater, Tile::DeepWater,
+            Tile::ShortGrass, Tile::LongGrass, Tile::Tree, Tile::StairsDown, Tile::StairsUp,
+            Tile::Rock, Tile::CaveFloor, Tile::StoneWall, Tile::Rubble, Tile::Boulder, Tile::Bog,
+            Tile::Bridge,
+        ].iter().cloned().find(|t| format!("{:?}", t).eq_ignore_ascii_case(&name))
+    }
+
     pub fn render(self) -> Cell {
         match self {
             Tile::Wall => Cell {
@@ -66,6 +87,60 @@ impl Tile {
                 bg: Color::Black,
                 bold: false,
             },
+            Tile::StairsDown => Cell {
+                ch: '>',
+                fg: Color::White,
+                bg: Color::Black,
+                bold: true,
+            },
+            Tile::StairsUp => Cell {
+                ch: '<',
+                fg: Color::White,
+                bg: Color::Black,
+                bold: true,
+            },
+            Tile::Rock => Cell {
+                ch: '^',
+                fg: Color::White,
+                bg: Color::Black,
+                bold: false,
+            },
+            Tile::CaveFloor => Cell {
+                ch: '.',
+                fg: Color::White,
+                bg: Color::Black,
+                bold: false,
+            },
+            Tile::StoneWall => Cell {
+                ch: '#',
+                fg: Color::White,
+                bg: Color::Black,
+                bold: false,
+            },
+            Tile::Rubble => Cell {
+                ch: ',',
+                fg: Color::White,
+                bg: Color::Black,
+                bold: false,
+            },
+            Tile::Boulder => Cell {
+                ch: 'o',
+                fg: Color::White,
+                bg: Color::Black,
+                bold: false,
+            },
+            Tile::Bog => Cell {
+                ch: '~',
+                fg: Color::Green,
+                bg: Color::Black,
+                bold: false,
+            },
+            Tile::Bridge => Cell {
+                ch: '=',
+                fg: Color::Yellow,
+                bg: Color::Blue,
+                bold: true,
+            },
         }
     }
 
@@ -90,10 +165,24 @@ impl Tile {
 
     pub fn is_walkable(self) -> bool {
         // TODO: account for swimming and flying critters
-        self != Tile::Wall && self != Tile::DeepWater && self != Tile::Tree
+        ![
+            Tile::Wall, Tile::DeepWater, Tile::Tree, Tile::Rock, Tile::StoneWall, Tile::Boulder,
+        ].contains(&self)
     }
 
+    // Boulders are too big to climb over, but not to see over
     pub fn is_obstructed(self) -> bool {
-        self == Tile::Wall || self == Tile::Tree
+        [Tile::Wall, Tile::Tree, Tile::Rock, Tile::StoneWall].contains(&self)
+    }
+
+    /// How many turns it takes to step onto this tile, if it can be done at
+    /// all.
+    pub fn move_time(self) -> u32 {
+        match self {
+            Tile::Bog => 2,
+            // On a raft
+            Tile::DeepWater => 3,
+            _ => 1,
+        }
     }
 }
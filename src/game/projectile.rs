@@ -0,0 +1,243 @@
+use rand::Rng;
+
+use super::*;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ProjectileKind {
+    Rock,
+    Arrow,
+}
+
+impl ProjectileKind {
+    pub fn item(self) -> EntityType {
+        match self {
+            ProjectileKind::Rock => EntityType::Rock,
+            ProjectileKind::Arrow => EntityType::Arrow,
+        }
+    }
+
+    // One in this many arrows breaks when it lands
+    fn break_chance(self) -> Option<u32> {
+        match self {
+            ProjectileKind::Rock => None,
+            ProjectileKind::Arrow => Some(4),
+        }
+    }
+
+    fn damage(self) -> i8 {
+        match self {
+            ProjectileKind::Rock => 1,
+            ProjectileKind::Arrow => 2,
+        }
+    }
+
+    // Percent chance to hit an adjacent actor, dropping off with distance
+    fn accuracy(self, distance: i32) -> i32 {
+        match self {
+            ProjectileKind::Rock => 85 - 5*distance,
+            ProjectileKind::Arrow => 95 - 3*distance,
+        }
+    }
+}
+
+/// What happened to a projectile after it was fired.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectileReport {
+    pub kind: ProjectileKind,
+    pub from: Position,
+    /// Every tile the projectile passed through, in order.
+    pub path: Vec<Position>,
+    pub hit: Option<Entity>,
+    pub missed: Vec<Entity>,
+    /// Where the projectile came to rest, unless it broke or sank.
+    pub landed: Option<Position>,
+}
+
+// Rocks are thrown at a tile and stop there, arrows keep flying until they
+// run out of range. Either way, obstructed tiles stop the flight.
+fn flight<'a>(g: &'a Game, from: Position, kind: ProjectileKind, target: Position)
+    -> Box<dyn Iterator<Item=Position> + 'a>
+{
+    let line: Box<dyn Iterator<Item=Position>> = match kind {
+        ProjectileKind::Rock => Box::new(from.line_to(target)),
+        ProjectileKind::Arrow if from == target => Box::new(None.into_iter()),
+        ProjectileKind::Arrow => Box::new(from.ray_towards(target).take(BOW_RANGE)),
+    };
+    Box::new(line.take_while(move |&pos| !g.get_tile(pos).is_obstructed()))
+}
+
+/// The path a projectile would take if it hits the first actor in its way.
+pub fn preview(g: &Game, from: Position, kind: ProjectileKind, target: Position) -> Vec<Position> {
+    let mut path = vec![];
+    for pos in flight(g, from, kind, target) {
+        path.push(pos);
+        if g.get_actor_by_position(pos).is_ok() {
+            break;
+        }
+    }
+    path
+}
+
+pub fn fire(g: &mut Game, shooter: Entity, item: Entity, kind: ProjectileKind, target: Position)
+    -> ActionResult<ProjectileReport>
+{
+    let from = g.entity_position(shooter).map_err(|_| ActionError::Unplaced(shooter))?;
+    let flight: Vec<_> = flight(g, from, kind, target).collect();
+    let mut report = ProjectileReport {
+        kind,
+        from,
+        path: vec![],
+        hit: None,
+        missed: vec![],
+        landed: None,
+    };
+
+    for pos in flight {
+        report.path.push(pos);
+        if let Ok(actor) = g.get_actor_by_position(pos) {
+            let distance = pos.chebyshev_distance(from);
+            if g.rand().gen_range(0, 100) < kind.accuracy(distance) {
+                // TODO: really ignore result?
+                let _ = g.attack_entity(shooter, actor, kind.damage());
+                report.hit = Some(actor);
+                break;
+            }
+            report.missed.push(actor);
+        }
+    }
+
+    let landing = report.path.last().cloned().unwrap_or(from);
+    let broken = kind.break_chance().map(|n| g.rand().gen_range(0, n) == 0).unwrap_or(false);
+    if broken || g.get_tile(landing) == Tile::DeepWater {
+        g.world.despawn(item);
+    } else {
+        g.world.set_location(item, Location::Position(landing));
+        report.landed = Some(landing);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, StdRng};
+
+    use super::*;
+    use game::tests::{field, give, CENTER};
+
+    fn east(x: i32) -> Position {
+        Position { x, y: 0 }
+    }
+
+    fn shoot(g: &mut Game, kind: ProjectileKind, target: Position) -> (Entity, ProjectileReport) {
+        let player = g.player().unwrap();
+        let item = give(g, kind.item());
+        (item, fire(g, player, item, kind, target).unwrap())
+    }
+
+    #[test]
+    fn rocks_land_on_their_target() {
+        let mut g = field(10);
+        let (rock, report) = shoot(&mut g, ProjectileKind::Rock, east(3));
+        assert_eq!(report.path, vec![east(1), east(2), east(3)]);
+        assert_eq!(report.landed, Some(east(3)));
+        assert_eq!(g.entity_position(rock), Ok(east(3)));
+    }
+
+    #[test]
+    fn arrows_fly_past_their_target() {
+        let mut g = field(10);
+        let (_, report) = shoot(&mut g, ProjectileKind::Arrow, east(2));
+        let expected: Vec<_> = (1..=BOW_RANGE as i32).map(east).collect();
+        assert_eq!(report.path, expected);
+        assert_eq!(preview(&g, CENTER, ProjectileKind::Arrow, east(2)), expected);
+    }
+
+    #[test]
+    fn trees_and_walls_stop_projectiles() {
+        let mut g = field(10);
+        g.world.entity_mut(east(3)).insert(Tile::Tree);
+        let (_, report) = shoot(&mut g, ProjectileKind::Rock, east(5));
+        assert_eq!(report.path, vec![east(1), east(2)]);
+        assert_eq!(report.landed, Some(east(2)));
+
+        g.world.entity_mut(east(3)).insert(Tile::StoneWall);
+        assert_eq!(preview(&g, CENTER, ProjectileKind::Arrow, east(5)), vec![east(1), east(2)]);
+    }
+
+    #[test]
+    fn rocks_hit_or_miss() {
+        let (mut hits, mut misses) = (0, 0);
+        for seed in 0..40 {
+            let mut g = field(10);
+            *g.rand() = StdRng::from_seed(&[seed]);
+            g.put_entity(EntityType::Rat, east(4));
+            let rat = g.get_actor_by_position(east(4)).unwrap();
+            let (_, report) = shoot(&mut g, ProjectileKind::Rock, east(4));
+            if report.hit == Some(rat) {
+                hits += 1;
+                assert!(report.missed.is_empty());
+                assert_eq!(report.landed, Some(east(4)));
+                assert_eq!(g.world.entity(rat).get::<Damage>(), Ok(&Damage(1)));
+            } else {
+                misses += 1;
+                assert_eq!(report.missed, vec![rat]);
+                assert_eq!(report.path.last(), Some(&east(4)));
+                assert!(g.world.entity(rat).get::<Damage>().is_err());
+            }
+        }
+        // Rocks hit 65% of the time from 4 tiles away
+        assert!(hits > 15 && misses > 5, "{} hits, {} misses", hits, misses);
+    }
+
+    #[test]
+    fn arrows_miss_past_their_target() {
+        let mut missed = false;
+        for seed in 0..40 {
+            let mut g = field(10);
+            *g.rand() = StdRng::from_seed(&[seed]);
+            g.put_entity(EntityType::Rat, east(2));
+            let (_, report) = shoot(&mut g, ProjectileKind::Arrow, east(2));
+            if report.hit.is_none() {
+                assert_eq!(report.path.len(), BOW_RANGE);
+                missed = true;
+            }
+        }
+        assert!(missed);
+    }
+
+    #[test]
+    fn arrows_sometimes_break() {
+        let mut g = field(10);
+        let (mut broken, mut landed) = (0, 0);
+        for _ in 0..40 {
+            let (arrow, report) = shoot(&mut g, ProjectileKind::Arrow, east(1));
+            match report.landed {
+                Some(pos) => {
+                    landed += 1;
+                    assert_eq!(g.entity_position(arrow), Ok(pos));
+                }
+                None => {
+                    broken += 1;
+                    assert!(g.world.entity(arrow).get::<EntityType>().is_err());
+                }
+            }
+        }
+        assert!(broken > 0 && landed > broken, "{} broken, {} landed", broken, landed);
+
+        // Rocks don't
+        for _ in 0..40 {
+            assert!(shoot(&mut g, ProjectileKind::Rock, east(1)).1.landed.is_some());
+        }
+    }
+
+    #[test]
+    fn deep_water_swallows_projectiles() {
+        let mut g = field(10);
+        g.world.entity_mut(east(3)).insert(Tile::DeepWater);
+        let (rock, report) = shoot(&mut g, ProjectileKind::Rock, east(3));
+        assert_eq!(report.path.last(), Some(&east(3)));
+        assert_eq!(report.landed, None);
+        assert!(g.world.entity(rock).get::<EntityType>().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::fmt;
+use rand::Rng;
+
+use ::engine::*;
+use super::{Game, GameWorld, Contents, Entity, EntityType, Tile};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rect {
+    pub min_x: i32,
+    pub max_x: i32,
+    pub min_y: i32,
+    pub max_y: i32,
+}
+
+impl Rect {
+    /// Every position no more than `radius` steps from `center`, counting
+    /// diagonal steps.
+    pub fn around(center: Position, radius: i32) -> Rect {
+        Rect {
+            min_x: center.x - radius,
+            max_x: center.x + radius,
+            min_y: center.y - radius,
+            max_y: center.y + radius,
+        }
+    }
+
+    pub fn extend(&mut self, pos: Position) {
+        self.min_x = ::std::cmp::min(self.min_x, pos.x);
+        self.max_x = ::std::cmp::max(self.max_x, pos.x);
+        self.min_y = ::std::cmp::min(self.min_y, pos.y);
+        self.max_y = ::std::cmp::max(self.max_y, pos.y);
+    }
+
+    pub fn contains(&self, pos: Position) -> bool {
+        self.min_x <= pos.x && pos.x <= self.max_x && self.min_y <= pos.y && pos.y <= self.max_y
+    }
+
+    /// Row by row, from the top left.
+    pub fn positions(self) -> impl Iterator<Item=Position> {
+        (self.min_y..=self.max_y).flat_map(move |y| {
+            (self.min_x..=self.max_x).map(move |x| Position { x, y })
+        })
+    }
+}
+
+impl From<Position> for Rect {
+    fn from(pos: Position) -> Rect {
+        Rect::around(pos, 0)
+    }
+}
+
+/// Whether `b` is `a` or one of its eight neighbours.
+pub fn is_adjacent(a: Position, b: Position) -> bool {
+    a.chebyshev_distance(b) <= 1
+}
+
+/// Every position that has a tile, so that one can be picked at random
+/// without walking the whole map. Kept up to date by `on_tile_changed`.
+#[derive(Clone, Default, PartialEq)]
+pub struct TileIndex {
+    positions: Vec<Position>,
+    indexes: HashMap<Position, usize>,
+}
+
+// Printing thousands of positions isn't much use to anyone
+impl fmt::Debug for TileIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TileIndex({} tiles)", self.positions.len())
+    }
+}
+
+impl Resource for TileIndex {}
+
+impl TileIndex {
+    fn insert(&mut self, pos: Position) {
+        if !self.indexes.contains_key(&pos) {
+            self.indexes.insert(pos, self.positions.len());
+            self.positions.push(pos);
+        }
+    }
+
+    fn remove(&mut self, pos: Position) {
+        if let Some(index) = self.indexes.remove(&pos) {
+            self.positions.swap_remove(index);
+            if let Some(&moved) = self.positions.get(index) {
+                self.indexes.insert(moved, index);
+            }
+        }
+    }
+}
+
+pub fn on_tile_changed(w: &mut GameWorld, pos: Position, event: &ComponentEvent<Tile>) {
+    match *event {
+        ComponentEvent::Inserted(_) => w.resource_mut::<TileIndex>().insert(pos),
+        ComponentEvent::Removed(_) => w.resource_mut::<TileIndex>().remove(pos),
+        ComponentEvent::Modified { .. } => {}
+    }
+}
+
+impl Game {
+    /// Any position that has a tile, all equally likely.
+    pub fn random_tile(&mut self) -> Option<Position> {
+        let count = self.world.resource::<TileIndex>().positions.len();
+        if count == 0 {
+            return None;
+        }
+        let index = self.rand().gen_range(0, count);
+        Some(self.world.resource::<TileIndex>().positions[index])
+    }
+
+    fn entities_at(&self, pos: Position) -> impl Iterator<Item=Entity> + '_ {
+        self.world.entity(pos).get::<Contents>().ok().into_iter()
+            .flat_map(|c| c.0.iter().cloned())
+    }
+
+    /// Entities lying directly on a position no further than `radius` from
+    /// `center` as the crow flies, nearest first.
+    pub fn entities_within(&self, center: Position, radius: i32) -> Vec<(Entity, Position)> {
+        let mut entities: Vec<_> = center.circle(radius)
+            .flat_map(|pos| self.entities_at(pos).map(move |id| (id, pos)))
+            .collect();
+        // sort_by_key is stable, so ties stay in row order
+        entities.sort_by_key(|&(_, pos)| center.distance_sq(pos));
+        entities
+    }
+
+    /// The nearest entity within `radius` of `center` whose type matches.
+    pub fn nearest<P>(&self, center: Position, radius: i32, mut predicate: P) -> Option<(Entity, Position)>
+        where P: FnMut(EntityType) -> bool
+    {
+        self.entities_within(center, radius).into_iter().find(|&(id, _)| {
+            self.world.entity(id).get::<EntityType>().map(|&t| predicate(t)).unwrap_or(false)
+        })
+    }
+}
@@ -1,5 +1,5 @@
 use ::engine::Component;
 
-#[derive(Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Damage(pub i8);
 impl Component for Damage {}
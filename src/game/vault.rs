@@ -0,0 +1,257 @@
+use std::fmt;
+use std::fs;
+
+use ::engine::*;
+use super::Tile;
+use super::map_config::{number, parse_loot, ConfigError, Loot};
+
+/// A hand drawn piece of map, with things placed on it.
+#[derive(Clone, PartialEq)]
+pub struct Vault {
+    pub name: String,
+    /// How likely it is to be picked, compared to the others.
+    pub weight: u32,
+    /// How far below the surface it can turn up.
+    pub min_level: u32,
+    // Relative to the middle of the map it was drawn with
+    cells: Vec<(Offset, Stamp)>,
+}
+
+/// What a vault puts on one tile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stamp {
+    pub tile: Tile,
+    pub loot: Vec<Loot>,
+}
+
+// The whole map isn't much use to anyone
+impl fmt::Debug for Vault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Vault({}, weight {})", self.name, self.weight)
+    }
+}
+
+impl Vault {
+    /// Every tile the vault covers, turned a quarter turn clockwise `turns`
+    /// times and then flipped left to right if `mirrored`.
+    pub fn cells(&self, turns: u32, mirrored: bool) -> impl Iterator<Item=(Offset, &Stamp)> {
+        self.cells.iter().map(move |&(offset, ref stamp)| {
+            let mut offset = offset;
+            for _ in 0..turns % 4 {
+                offset = Offset { x: -offset.y, y: offset.x };
+            }
+            if mirrored {
+                offset.x = -offset.x;
+            }
+            (offset, stamp)
+        })
+    }
+}
+
+/// The vaults that come with the game, from vaults.txt.
+pub fn builtin_vaults() -> Vec<Vault> {
+    parse_vaults(include_str!("vaults.txt")).expect("vaults.txt should be valid")
+}
+
+pub fn load_vaults(path: &str) -> Result<Vec<Vault>, ConfigError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| ConfigError::Io(String::from(path), e))?;
+    parse_vaults(&text)
+}
+
+fn default_tile(ch: char) -> Option<Tile> {
+    Some(match ch {
+        '.' => Tile::Ground,
+        ',' => Tile::Rubble,
+        '\'' => Tile::ShortGrass,
+        '"' => Tile::LongGrass,
+        '=' => Tile::ShallowWater,
+        '~' => Tile::Bog,
+        'T' => Tile::Tree,
+        '^' => Tile::Rock,
+        '#' => Tile::StoneWall,
+        'o' => Tile::Boulder,
+        _ => return None,
+    })
+}
+
+// Settings and legend entries, before the map is put together
+struct Draft {
+    name: String,
+    line: usize,
+    weight: u32,
+    min_level: u32,
+    legend: Vec<(char, Stamp)>,
+    rows: Vec<(usize, String)>,
+}
+
+impl Draft {
+    fn finish(self) -> Result<Vault, ConfigError> {
+        if self.rows.is_empty() {
+            return Err(ConfigError::Parse {
+                line: self.line,
+                reason: format!("vault {} has no map", self.name),
+            });
+        }
+        let height = self.rows.len() as i32;
+        let width = self.rows.iter().map(|(_, row)| row.chars().count()).max().unwrap_or(0) as i32;
+        let mut cells = vec![];
+        for (y, &(line, ref row)) in self.rows.iter().enumerate() {
+            for (x, ch) in row.chars().enumerate() {
+                if ch == ' ' {
+                    continue;
+                }
+                let stamp = match self.legend.iter().find(|&&(c, _)| c == ch) {
+                    Some((_, stamp)) => stamp.clone(),
+                    None => match default_tile(ch) {
+                        Some(tile) => Stamp { tile, loot: vec![] },
+                        None => return Err(ConfigError::Parse {
+                            line,
+                            reason: format!("{} isn't a tile or in the legend", ch),
+                        }),
+                    },
+                };
+                let offset = Offset { x: x as i32 - width/2, y: y as i32 - height/2 };
+                cells.push((offset, stamp));
+            }
+        }
+        Ok(Vault { name: self.name, weight: self.weight, min_level: self.min_level, cells })
+    }
+}
+
+/// Reads vaults, each of which is a `[name]` line, `key = value` lines, and
+/// then the rows of its map. The keys are `weight`, `min_level`, and single
+/// characters, which say what a character in the map stands for: a tile and
+/// then any loot, like `D = ground dragon diamond:1-2`. Characters that aren't
+/// in the legend are tiles, from `.,'"=~T^#o`. Lines starting with ';' are
+/// ignored, and so are blank ones.
+pub fn parse_vaults(text: &str) -> Result<Vec<Vault>, ConfigError> {
+    let mut vaults = vec![];
+    let mut draft: Option<Draft> = None;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |reason| ConfigError::Parse { line: line_number, reason };
+        if line.trim().is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.trim_end().ends_with(']') {
+            if let Some(draft) = draft.take() {
+                vaults.push(draft.finish()?);
+            }
+            let name = line.trim_end();
+            draft = Some(Draft {
+                name: String::from(&name[1..name.len() - 1]),
+                line: line_number,
+                weight: 1,
+                min_level: 0,
+                legend: vec![],
+                rows: vec![],
+            });
+            continue;
+        }
+        let draft = draft.as_mut().ok_or_else(|| error(String::from("expected a [name] first")))?;
+
+        let mut parts = line.splitn(2, " = ");
+        let key = parts.next().unwrap_or("").trim();
+        match parts.next().map(str::trim) {
+            Some(value) if draft.rows.is_empty() => match key {
+                "weight" => { draft.weight = number(value).map_err(error)?; }
+                "min_level" => { draft.min_level = number(value).map_err(error)?; }
+                _ if key.chars().count() == 1 => {
+                    let ch = key.chars().next().unwrap();
+                    let mut words = value.split_whitespace();
+                    let tile_name = words.next().unwrap_or("");
+                    let tile = Tile::from_name(tile_name)
+                        .ok_or_else(|| error(format!("unknown tile {}", tile_name)))?;
+                    let loot = words.map(parse_loot).collect::<Result<_, _>>().map_err(error)?;
+                    draft.legend.push((ch, Stamp { tile, loot }));
+                }
+                _ => return Err(error(format!("unknown key {}", key))),
+            },
+            _ => draft.rows.push((line_number, String::from(line.trim_end()))),
+        }
+    }
+    if let Some(draft) = draft {
+        vaults.push(draft.finish()?);
+    }
+    Ok(vaults)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::EntityType;
+
+    const CAMP: &str = "
+; Left behind in a hurry
+[camp]
+weight = 3
+min_level = 1
+* = rubble diamond:1-2
+
+#.
+o*
+";
+
+    fn tiles(vault: &Vault, turns: u32, mirrored: bool) -> Vec<(i32, i32, Tile)> {
+        vault.cells(turns, mirrored).map(|(o, stamp)| (o.x, o.y, stamp.tile)).collect()
+    }
+
+    fn parse_error(text: &str) -> Option<usize> {
+        match parse_vaults(text) {
+            Err(ConfigError::Parse { line, .. }) => Some(line),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_settings_legend_and_map() {
+        let vaults = parse_vaults(CAMP).unwrap();
+        assert_eq!(vaults.len(), 1);
+        let camp = &vaults[0];
+        assert_eq!((camp.name.as_str(), camp.weight, camp.min_level), ("camp", 3, 1));
+        assert_eq!(tiles(camp, 0, false), vec![
+            (-1, -1, Tile::StoneWall), (0, -1, Tile::Ground),
+            (-1, 0, Tile::Boulder), (0, 0, Tile::Rubble),
+        ]);
+        let loot = &camp.cells(0, false).last().unwrap().1.loot;
+        assert_eq!(loot, &vec![Loot { entity: EntityType::Diamond, min: 1, max: 2 }]);
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(parse_error("weight = 2\n[camp]\n."), Some(1));
+        assert_eq!(parse_error("[camp]\nweight = lots\n."), Some(2));
+        assert_eq!(parse_error("[camp]\n* = lava\n."), Some(2));
+        assert_eq!(parse_error("[camp]\n.\n.?"), Some(3));
+        assert_eq!(parse_error("[camp]\n.\n\n[empty]\nweight = 2\n\n[last]\n."), Some(4));
+    }
+
+    #[test]
+    fn builtin_vaults_parse() {
+        assert!(!builtin_vaults().is_empty());
+    }
+
+    #[test]
+    fn turns_clockwise_then_mirrors() {
+        let camp = &parse_vaults(CAMP).unwrap()[0];
+        assert_eq!(tiles(camp, 1, false), vec![
+            (1, -1, Tile::StoneWall), (1, 0, Tile::Ground),
+            (0, -1, Tile::Boulder), (0, 0, Tile::Rubble),
+        ]);
+        assert_eq!(tiles(camp, 2, false), vec![
+            (1, 1, Tile::StoneWall), (0, 1, Tile::Ground),
+            (1, 0, Tile::Boulder), (0, 0, Tile::Rubble),
+        ]);
+        assert_eq!(tiles(camp, 0, true), vec![
+            (1, -1, Tile::StoneWall), (0, -1, Tile::Ground),
+            (1, 0, Tile::Boulder), (0, 0, Tile::Rubble),
+        ]);
+        assert_eq!(tiles(camp, 1, true), vec![
+            (-1, -1, Tile::StoneWall), (-1, 0, Tile::Ground),
+            (0, -1, Tile::Boulder), (0, 0, Tile::Rubble),
+        ]);
+        assert_eq!(tiles(camp, 4, false), tiles(camp, 0, false));
+    }
+}
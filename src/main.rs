@@ -3,8 +3,9 @@
 extern crate pancurses;
 extern crate rand;
 
+use std::time::{Duration, Instant};
+
 use pancurses::{Input, Window};
-use rand::Rng;
 
 #[macro_use]
 mod engine;
@@ -13,15 +14,312 @@ use engine::*;
 mod game;
 use game::*;
 
-#[derive(Eq, PartialEq)]
+mod challenge;
+use challenge::Challenge;
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Aim {
+    Throw,
+    Fire,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
 enum InputMode {
     Normal,
-    Fire,
-    Throw(Position),
+    Target(Aim, Position),
+    // Debug builds only
+    Inspect(Position),
     None,
 }
 
+impl Aim {
+    fn kind(self) -> ProjectileKind {
+        match self {
+            Aim::Throw => ProjectileKind::Rock,
+            Aim::Fire => ProjectileKind::Arrow,
+        }
+    }
+
+    fn is_legal(self, legal: &[Action]) -> bool {
+        legal.iter().any(|&action| matches!(
+            (self, action),
+            (Aim::Throw, Action::ThrowRock(_)) | (Aim::Fire, Action::FireBow(_))
+        ))
+    }
+
+    fn action(self, target: Position) -> Action {
+        match self {
+            Aim::Throw => Action::ThrowRock(target),
+            Aim::Fire => Action::FireBow(target),
+        }
+    }
+}
+
+// Start on the last target if it's still around, otherwise the nearest actor.
+fn initial_target(g: &Game, last_target: Option<Entity>) -> Option<Position> {
+    let actors = g.visible_actors();
+    actors.iter()
+        .find(|&&(id, _)| Some(id) == last_target)
+        .or_else(|| actors.first())
+        .map(|&(_, pos)| pos)
+        .or_else(|| g.player_position().ok())
+}
+
+fn cycle_target(g: &Game, current: Position, forward: bool) -> Position {
+    let actors = g.visible_actors();
+    if actors.is_empty() {
+        return current;
+    }
+    let index = match actors.iter().position(|&(_, pos)| pos == current) {
+        Some(i) if forward => (i + 1) % actors.len(),
+        Some(i) => (i + actors.len() - 1) % actors.len(),
+        None if forward => 0,
+        None => actors.len() - 1,
+    };
+    actors[index].1
+}
+
+fn projectile_glyph(report: &ProjectileReport) -> char {
+    if report.kind == ProjectileKind::Rock {
+        return EntityType::Rock.data().ch;
+    }
+    let end = report.path.last().cloned().unwrap_or(report.from);
+    let Offset { x: dx, y: dy } = end - report.from;
+    if dy.abs() > 2*dx.abs() {
+        '|'
+    } else if dx.abs() > 2*dy.abs() {
+        '-'
+    } else if (dx > 0) == (dy > 0) {
+        '\\'
+    } else {
+        '/'
+    }
+}
+
+// Flies the projectile over the screen as it was before the turn was taken
+fn animate_projectile(window: &Window, report: &ProjectileReport, x_offset: i32, y_offset: i32) {
+    let ch = projectile_glyph(report);
+    let color = report.kind.item().data().color.unwrap_or(Color::White);
+    for pos in &report.path {
+        let (y, x) = (pos.y - y_offset + 1, pos.x - x_offset);
+        let old = window.mvinch(y, x);
+        put_cell(window, y, x, Cell { ch, fg: color, bg: Color::Black, bold: true });
+        window.refresh();
+        pancurses::napms(25);
+        window.mvaddch(y, x, old);
+    }
+}
+
+// If there's nothing to aim with, the error explains why.
+fn enter_targeting(g: &mut Game, aim: Aim, last_target: Option<Entity>) -> Result<InputMode, String> {
+    let target = initial_target(g, last_target).ok_or_else(|| String::from("You can't aim now."))?;
+    if aim.is_legal(g.legal_actions()) {
+        Ok(InputMode::Target(aim, target))
+    } else {
+        Err(g.check_action(aim.action(target)).err()
+            .map(|e| e.to_string())
+            .unwrap_or_else(|| String::from("You can't aim now.")))
+    }
+}
+
+// How many turns debug builds can undo
+const UNDO_LIMIT: usize = 100;
+
+// Debug builds remember the game as it was before each turn.
+fn take_turn(g: &mut Game, action: Action, message: &mut Option<String>, history: &mut Vec<Game>)
+    -> Option<TurnReport>
+{
+    let before = if cfg!(debug_assertions) { Some(g.snapshot()) } else { None };
+    match g.take_turn(action) {
+        Ok(report) => {
+            if let Some(before) = before {
+                if history.len() == UNDO_LIMIT {
+                    history.remove(0);
+                }
+                history.push(before);
+            }
+            Some(report)
+        }
+        Err(e) => {
+            *message = Some(e.to_string());
+            None
+        }
+    }
+}
+
+// Lists everything at `pos` down the right side of the map
+fn draw_inspector(window: &Window, g: &Game, pos: Position) {
+    let (max_y, max_x) = window.get_max_yx();
+    let lines = g.inspect(pos);
+    let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as i32;
+    let width = ::std::cmp::max(::std::cmp::min(longest + 2, max_x / 2), 0);
+    let text_width = (width as usize).saturating_sub(1);
+    for (y, line) in (1..max_y-1).zip(&lines) {
+        let text: String = line.chars().take(text_width).collect();
+        let text = format!(" {:1$}", text, text_width);
+        put_str(window, y, max_x - width, &text, Color::White, false);
+    }
+}
+
+fn put_cell(window: &Window, y: i32, x: i32, c: Cell) {
+    window.mvaddch(y, x, c.ch);
+    let attr = if c.bold { pancurses::A_BOLD } else { pancurses::A_NORMAL };
+    window.mvchgat(y, x, 1, attr, (c.fg as i16) + (c.bg as i16)*8 + 1);
+}
+
+fn put_str(window: &Window, y: i32, x: i32, s: &str, fg: Color, bold: bool) {
+    window.mvaddstr(y, x, s);
+    let attr = if bold { pancurses::A_BOLD } else { pancurses::A_NORMAL };
+    window.mvchgat(y, x, s.chars().count() as i32, attr, (fg as i16) + 1);
+}
+
+// Shows the latest message if there is one, otherwise what the player can
+// do, with anything that isn't currently possible greyed out.
+fn draw_command_bar(
+    window: &Window, g: &mut Game, mode: InputMode, message: &Option<String>, challenge: &Challenge,
+) {
+    let y = window.get_max_y() - 1;
+    if let Some(ref message) = *message {
+        put_str(window, y, 1, message, Color::White, true);
+        return;
+    }
+    match mode {
+        InputMode::None => {}
+        InputMode::Inspect(_) => {
+            put_str(window, y, 1, "Wizard: move to inspect a tile  Space: done", Color::White, true);
+        }
+        InputMode::Target(aim, target) => {
+            let text = match g.check_action(aim.action(target)) {
+                Ok(()) => String::from("Tab: next target  Enter: shoot  Space: cancel"),
+                Err(e) => e.to_string(),
+            };
+            put_str(window, y, 1, &text, Color::White, true);
+        }
+        InputMode::Normal => {
+            let legal = g.legal_actions();
+            let commands = [
+                ("R:recall", legal.contains(&Action::ReadScroll)),
+                ("e:eat", legal.contains(&Action::EatHerb)),
+                ("g:get corpse", legal.contains(&Action::GetCorpse)),
+                ("d:drop corpse", legal.contains(&Action::DropCorpse)),
+                ("f:fire", Aim::Fire.is_legal(legal)),
+                ("t:throw", Aim::Throw.is_legal(legal)),
+                (">:descend", legal.contains(&Action::Descend)),
+                ("<:ascend", legal.contains(&Action::Ascend)),
+            ];
+            let mut x = 1;
+            for &(text, is_legal) in &commands {
+                // Bold black shows up as grey on most terminals
+                let fg = if is_legal { Color::White } else { Color::Black };
+                put_str(window, y, x, text, fg, true);
+                x += text.len() as i32 + 2;
+            }
+            // Out of the way in the corner, if there's room for it
+            let label = challenge.label();
+            let label_x = window.get_max_x() - label.len() as i32 - 1;
+            if label_x >= x {
+                put_str(window, y, label_x, &label, Color::Black, true);
+            }
+        }
+    }
+}
+
+// Times map generation and turns over a fixed set of seeds, with the player
+// waiting until they die or the turns run out.
+fn bench(turns: u32, config: &MapGenConfig) {
+    let seeds = 8;
+    let (mut gen_time, mut turn_time) = (Duration::new(0, 0), Duration::new(0, 0));
+    let mut turns_taken = 0;
+    let mut system_times: Vec<(&str, Duration)> = vec![];
+    let mut connectivity = ConnectivityReport::default();
+    for seed in 0..seeds {
+        let start = Instant::now();
+        let mut g = Game::with_config(seed, config.clone());
+        gen_time += start.elapsed();
+
+        let report = g.connectivity();
+        connectivity.reachable_tiles += report.reachable_tiles;
+        connectivity.paths_carved += report.paths_carved;
+        connectivity.tiles_carved += report.tiles_carved;
+        connectivity.piles_moved += report.piles_moved;
+        connectivity.diamonds += report.diamonds;
+        connectivity.stranded_diamonds += report.stranded_diamonds;
+        connectivity.reachable_diamonds += report.reachable_diamonds;
+
+        let start = Instant::now();
+        for _ in 0..turns {
+            if g.take_turn(Action::Wait).is_err() {
+                break;
+            }
+            turns_taken += 1;
+        }
+        turn_time += start.elapsed();
+
+        for timing in g.system_timings() {
+            match system_times.iter_mut().find(|&&mut (name, _)| name == timing.name) {
+                Some(&mut (_, ref mut total)) => *total += timing.total,
+                None => system_times.push((timing.name, timing.total)),
+            }
+        }
+    }
+    // No turns are taken when `turns` is 0
+    let divisor = ::std::cmp::max(turns_taken, 1);
+    println!("map generation: {:?} per game", gen_time / seeds as u32);
+    println!(
+        "  {} paths carved through {} tiles, {} piles moved, {} of {} diamonds stranded, {} reachable",
+        connectivity.paths_carved, connectivity.tiles_carved, connectivity.piles_moved,
+        connectivity.stranded_diamonds, connectivity.diamonds, connectivity.reachable_diamonds,
+    );
+    println!("{} turns: {:?} per turn", turns_taken, turn_time / divisor);
+    for (name, total) in system_times {
+        println!("  {}: {:?} per turn", name, total / divisor);
+    }
+}
+
 fn main() {
+    let args: Vec<String> = ::std::env::args().collect();
+    let mut challenge = if let Some(i) = args.iter().position(|a| a == "--seed") {
+        match args.get(i + 1).and_then(|arg| Challenge::parse(arg)) {
+            Some(challenge) => challenge,
+            None => {
+                eprintln!("--seed needs a number or a seed code");
+                ::std::process::exit(1);
+            }
+        }
+    } else if let Some(i) = args.iter().position(|a| a == "--daily") {
+        match args.get(i + 1).filter(|arg| !arg.starts_with("--")) {
+            Some(date) => Challenge::daily(date),
+            None => Challenge::daily(&challenge::today()),
+        }
+    } else {
+        Challenge::random()
+    };
+    let scores_path = match args.iter().position(|a| a == "--scores") {
+        Some(i) => args.get(i + 1).cloned().unwrap_or_else(|| {
+            eprintln!("--scores needs a file");
+            ::std::process::exit(1);
+        }),
+        None => String::from("scores.txt"),
+    };
+    let config = match args.iter().position(|a| a == "--map") {
+        Some(i) => match args.get(i + 1).map(|arg| MapGenConfig::from_arg(arg)) {
+            Some(Ok(config)) => config,
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                ::std::process::exit(1);
+            }
+            None => {
+                eprintln!("--map needs a preset ({}) or a file", PRESETS.join(", "));
+                ::std::process::exit(1);
+            }
+        },
+        None => MapGenConfig::default(),
+    };
+    if let Some(i) = args.iter().position(|a| a == "--bench") {
+        bench(args.get(i + 1).and_then(|n| n.parse().ok()).unwrap_or(500), &config);
+        return;
+    }
+
     let window = pancurses::initscr();
     window.keypad(true);
     pancurses::noecho();
@@ -37,18 +335,16 @@ fn main() {
         pancurses::init_pair(i+1, fg, bg);
     }
 
-    fn put_cell(window: &Window, y: i32, x: i32, c: Cell) {
-        window.mvaddch(y, x, c.ch);
-        let attr = if c.bold { pancurses::A_BOLD } else { pancurses::A_NORMAL };
-        window.mvchgat(y, x, 1, attr, (c.fg as i16) + (c.bg as i16)*8 + 1);
-    }
-
     'application: loop {
         window.clear();
-        let mut g = Game::new(rand::thread_rng().gen());
+        let mut g = Game::with_config(challenge.seed, config.clone());
+        let mut score_note: Option<String> = None;
         let mut display_center = g.player_position()
             .unwrap_or(Position { x: 0, y: 0 });
         let mut mode = InputMode::Normal;
+        let mut last_target = None;
+        let mut message = None;
+        let mut history = vec![];
 
         'game: loop {
             window.erase();
@@ -88,8 +384,9 @@ fn main() {
             if let Some(status) = g.player_status() {
                 if status.recall_turns == Some(0) {
                     window.mvaddstr(0, 0, &format!(
-                        " You escaped with {} diamonds! Press 'N' to restart.",
+                        " You escaped with {} diamonds on {}! Press 'N' to restart.",
                         status.diamonds,
+                        challenge.label(),
                     ));
                     mode = InputMode::None;
                 } else if status.health > 0 {
@@ -104,7 +401,7 @@ fn main() {
                             ch: t.data().ch,
                             fg: t.data().color.unwrap_or(Color::White),
                             bg: Color::Black,
-                            bold: bold,
+                            bold,
                         }
                     );
 
@@ -117,6 +414,9 @@ fn main() {
                     if status.has_bow {
                         render_item(EntityType::Bow, 20+5, true);
                     }
+                    if status.has_raft {
+                        render_item(EntityType::Raft, 22+5, true);
+                    }
 
                     let render_count = |t: EntityType, x, count| {
                         window.attrset(if count > 0 { pancurses::A_BOLD } else { pancurses::A_NORMAL });
@@ -128,37 +428,81 @@ fn main() {
                     render_count(EntityType::Corpse, 43+5, status.corpses);
                     render_count(EntityType::Herb, 52+5, status.herbs);
                     render_count(EntityType::Diamond, 61+5, status.diamonds);
+                    if status.level > LevelId(0) {
+                        window.attrset(pancurses::A_BOLD);
+                        window.mvaddstr(0, 70+5, &format!(">: {:2}", status.level.0));
+                    }
                 } else {
                     window.mvaddstr(0, 0, &format!(
-                        " You died carrying {} diamonds. Press 'N' to restart.",
+                        " You died carrying {} diamonds on {}. Press 'N' to restart.",
                         status.diamonds,
+                        challenge.label(),
                     ));
                     mode = InputMode::None;
                 }
             }
 
-            let player_position = g.player_position();
+            // Recorded once, as soon as the game is over
+            if let (Some(status), None) = (g.player_status(), &score_note) {
+                let escaped = status.recall_turns == Some(0);
+                if escaped || status.health <= 0 {
+                    let saved = challenge::record_score(
+                        &scores_path, &challenge, &config.name, escaped, status.diamonds,
+                    );
+                    score_note = Some(match (saved, challenge::best_score(&scores_path, &challenge, &config.name)) {
+                        (Ok(()), Some(best)) => format!(
+                            "Score saved to {}. The best escape on this seed is {} diamonds.", scores_path, best,
+                        ),
+                        (Ok(()), None) => format!("Score saved to {}.", scores_path),
+                        (Err(e), _) => format!("Couldn't save your score to {}: {}", scores_path, e),
+                    });
+                }
+            }
+
+            let (path, target) = match mode {
+                InputMode::Target(aim, target) => {
+                    let color = if g.in_range(aim.kind(), target) {
+                        Color::Red
+                    } else {
+                        Color::Magenta
+                    };
+                    (g.projectile_path(aim.kind(), target), Some((target, color)))
+                }
+                InputMode::Inspect(pos) => (vec![], Some((pos, Color::Cyan))),
+                _ => (vec![], None),
+            };
             let (x_offset, y_offset) =
                 (display_center.x - max_x/2, display_center.y - max_y/2);
-            for y in 0..max_y-1 {
+            // Leave the bottom line for the command bar
+            for y in 0..max_y-2 {
                 for x in 0..max_x {
                     let pos = Position { x: x + x_offset, y: y + y_offset };
                     let mut cell = g.render(pos);
-                    if mode == InputMode::Fire && Ok(pos) == player_position {
-                        cell.bg = cell.fg;
-                        cell.fg = Color::Black;
-                        cell.bold = false;
+                    if path.contains(&pos) {
+                        cell.bg = Color::Yellow;
+                        if cell.fg == Color::Yellow { cell.fg = Color::Black; }
                     }
-                    if mode == InputMode::Throw(pos)  {
-                        cell.bg = Color::Red;
+                    if let Some((target, color)) = target {
+                        if pos == target {
+                            cell.bg = color;
+                        }
                     }
                     put_cell(&window, y+1, x, cell);
                 }
             }
 
+            if let InputMode::Inspect(pos) = mode {
+                draw_inspector(&window, &g, pos);
+            }
+            if let Some(ref note) = score_note {
+                message = Some(note.clone());
+            }
+            draw_command_bar(&window, &mut g, mode, &message, &challenge);
+
             window.refresh();
 
             let key = window.getch();
+            message = None;
 
             let dir = key.and_then(|key| match key {
                 // arrow keys
@@ -217,42 +561,23 @@ fn main() {
                 _ => None,
             });
 
-            #[allow(unused_must_use)]  // TODO: handle errors?
             match mode {
                 InputMode::None => {}
-                InputMode::Throw(pos) => {
+                InputMode::Inspect(pos) => {
                     if let Some(dir) = dir {
-                        mode = InputMode::Throw(pos.step(dir));
+                        mode = InputMode::Inspect(pos.step(dir));
                         continue 'game;
                     }
-                    match key {
-                        Some(Input::Character(' '))
-                            | Some(Input::Character('5'))
-                            | Some(Input::KeyB2) =>
-                        {
-                            mode = InputMode::Normal;
-                            continue 'game;
-                        }
-                        Some(Input::Character('f')) => {
-                            g.player_status().map(|status| {
-                                if status.has_bow && status.arrows > 0 {
-                                    mode = InputMode::Fire;
-                                }
-                            });
-                            continue 'game;
-                        }
-                        Some(Input::Character('t')) => {
-                            g.take_turn(Action::ThrowRock(pos));
-                            mode = InputMode::Normal;
-                            continue 'game;
-                        }
-                        _ => {}
+                    if let Some(Input::Character(' ')) | Some(Input::Character('5'))
+                        | Some(Input::KeyB2) | Some(Input::Character('~')) = key
+                    {
+                        mode = InputMode::Normal;
+                        continue 'game;
                     }
                 }
-                InputMode::Fire => {
+                InputMode::Target(aim, pos) => {
                     if let Some(dir) = dir {
-                        g.take_turn(Action::FireBow(dir));
-                        mode = InputMode::Normal;
+                        mode = InputMode::Target(aim, pos.step(dir));
                         continue 'game;
                     }
                     match key {
@@ -263,18 +588,38 @@ fn main() {
                             mode = InputMode::Normal;
                             continue 'game;
                         }
-                        Some(Input::Character('f')) => {
-                            mode = InputMode::Normal;
+                        Some(Input::Character('\t')) => {
+                            mode = InputMode::Target(aim, cycle_target(&g, pos, true));
                             continue 'game;
                         }
-                        Some(Input::Character('t')) => {
-                            if let Ok(player_position) = g.player_position() {
-                                g.player_status().map(|status| {
-                                    if status.rocks > 0 {
-                                        mode = InputMode::Throw(player_position);
-                                    }
-                                });
+                        Some(Input::KeyBTab) => {
+                            mode = InputMode::Target(aim, cycle_target(&g, pos, false));
+                            continue 'game;
+                        }
+                        Some(Input::Character('f')) | Some(Input::Character('t'))
+                            | Some(Input::Character('\n')) =>
+                        {
+                            let new_aim = match key {
+                                Some(Input::Character('f')) => Aim::Fire,
+                                Some(Input::Character('t')) => Aim::Throw,
+                                _ => aim,
+                            };
+                            if new_aim != aim {
+                                if new_aim.is_legal(g.legal_actions()) {
+                                    mode = InputMode::Target(new_aim, pos);
+                                }
+                                continue 'game;
+                            }
+                            if let Some(&(id, _)) = g.visible_actors().iter()
+                                .find(|&&(_, actor_pos)| actor_pos == pos)
+                            {
+                                last_target = Some(id);
+                            }
+                            let report = take_turn(&mut g, aim.action(pos), &mut message, &mut history);
+                            if let Some(projectile) = report.and_then(|r| r.projectile) {
+                                animate_projectile(&window, &projectile, x_offset, y_offset);
                             }
+                            mode = InputMode::Normal;
                             continue 'game;
                         }
                         _ => {}
@@ -282,53 +627,64 @@ fn main() {
                 }
                 InputMode::Normal => {
                     if let Some(dir) = dir {
-                        g.take_turn(Action::Move(dir));
+                        take_turn(&mut g, Action::Move(dir), &mut message, &mut history);
                         continue 'game;
                     }
                     if let Some(action) = match key {
                         Some(Input::Character(' '))
                             | Some(Input::Character('5'))
                             | Some(Input::KeyB2) => Some(Action::Wait),
-                        Some(Input::Character('e')) => {
-                            g.player_status().and_then(|status| {
-                                if status.health < status.max_health {
-                                    Some(Action::EatHerb)
-                                } else {
-                                    None
-                                }
-                            })
-                        },
+                        Some(Input::Character('e')) => Some(Action::EatHerb),
                         Some(Input::Character('R')) => Some(Action::ReadScroll),
                         Some(Input::Character('g')) => Some(Action::GetCorpse),
                         Some(Input::Character('d')) => Some(Action::DropCorpse),
+                        Some(Input::Character('>')) => Some(Action::Descend),
+                        Some(Input::Character('<')) => Some(Action::Ascend),
                         Some(Input::Character('t')) => {
-                            if let Ok(player_position) = g.player_position() {
-                                g.player_status().map(|status| {
-                                    if status.rocks > 0 {
-                                        mode = InputMode::Throw(player_position);
-                                    }
-                                });
+                            match enter_targeting(&mut g, Aim::Throw, last_target) {
+                                Ok(new_mode) => { mode = new_mode; }
+                                Err(e) => { message = Some(e); }
                             }
                             continue 'game;
                         },
                         Some(Input::Character('f')) => {
-                            g.player_status().map(|status| {
-                                if status.has_bow && status.arrows > 0 {
-                                    mode = InputMode::Fire;
+                            match enter_targeting(&mut g, Aim::Fire, last_target) {
+                                Ok(new_mode) => { mode = new_mode; }
+                                Err(e) => { message = Some(e); }
+                            }
+                            continue 'game;
+                        },
+                        Some(Input::Character('U')) if cfg!(debug_assertions) => {
+                            match history.pop() {
+                                Some(old) => {
+                                    let changes = old.diff(&g);
+                                    message = Some(format!(
+                                        "Undid a turn that changed {} entity components and {} tile components.",
+                                        changes.entities.len(),
+                                        changes.tiles.len(),
+                                    ));
+                                    g = old;
                                 }
-                            });
+                                None => { message = Some(String::from("Nothing to undo.")); }
+                            }
+                            continue 'game;
+                        },
+                        Some(Input::Character('~')) if cfg!(debug_assertions) => {
+                            if let Ok(pos) = g.player_position() {
+                                mode = InputMode::Inspect(pos);
+                            }
                             continue 'game;
                         },
                         _ => None,
                     } {
-                        g.take_turn(action);
+                        take_turn(&mut g, action, &mut message, &mut history);
                         continue 'game;
                     }
                 }
             }
 
-            match key {
-                Some(Input::Character(c)) => { match c {
+            if let Some(Input::Character(c)) = key {
+                match c {
                     'Q' => { break 'application; }
                     '\x1b' => {
                         // handle ESC, but ignore things like Alt+key
@@ -338,13 +694,16 @@ fn main() {
                         while window.getch().is_some() {}
                         window.nodelay(false);
                     }
-                    'N' => { break 'game; }
+                    'N' => {
+                        challenge = challenge.next();
+                        break 'game;
+                    }
                     _ => {}
-                }}
-                _ => {}
+                }
             }
         }
     }
 
     pancurses::endwin();
+    println!("Your last game was {}.", challenge.label());
 }